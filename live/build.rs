@@ -0,0 +1,176 @@
+//! Fingerprints the layout of `State` and the compiler for `abi::SOURCE_HASH`.
+//!
+//! The layout of a type can change without its size changing, so the host
+//! can't tell from the types alone whether a rebuilt library still agrees on
+//! `State`. The hash covers the text of the definitions `State` reaches: the
+//! items of this crate named in it, the ones named in those, and so on, with
+//! their doc comments left out. Names aren't resolved, every item of a name
+//! reached counts, and items made by macros aren't seen. The types of other
+//! crates are covered by the manifest and the lock file, the rest by the
+//! compiler and its flags.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::visit_mut::{self, VisitMut};
+use syn::{Attribute, Field, Item, Variant};
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Where the hash starts from.
+const ROOT: &str = "State";
+
+fn hash_bytes(mut hash: u64, bytes: &[u8]) -> u64{
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Every file below `dir`, sorted so the hash doesn't depend on the order the
+/// file system lists them in.
+fn files(dir: &Path, out: &mut Vec<PathBuf>){
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+/// Doc comments don't change a layout.
+fn strip_docs(attrs: &mut Vec<Attribute>){
+    attrs.retain(|attr| !attr.path().is_ident("doc"));
+}
+
+/// Strips the doc comments inside an item.
+struct StripDocs;
+impl VisitMut for StripDocs {
+    fn visit_field_mut(&mut self, field: &mut Field) {
+        strip_docs(&mut field.attrs);
+        visit_mut::visit_field_mut(self, field);
+    }
+    fn visit_variant_mut(&mut self, variant: &mut Variant) {
+        strip_docs(&mut variant.attrs);
+        visit_mut::visit_variant_mut(self, variant);
+    }
+}
+
+/// The text of the items a type can name, by name, from `items` and the
+/// modules inline in them: types, traits for `dyn` and consts for the
+/// lengths of arrays.
+fn definitions(items: &[Item], out: &mut BTreeMap<String, Vec<String>>){
+    for item in items {
+        let mut item = item.clone();
+        let (name, attrs) = match &mut item {
+            Item::Struct(i) => (i.ident.to_string(), &mut i.attrs),
+            Item::Enum(i) => (i.ident.to_string(), &mut i.attrs),
+            Item::Union(i) => (i.ident.to_string(), &mut i.attrs),
+            Item::Type(i) => (i.ident.to_string(), &mut i.attrs),
+            Item::Trait(i) => (i.ident.to_string(), &mut i.attrs),
+            Item::Const(i) => (i.ident.to_string(), &mut i.attrs),
+            Item::Mod(m) => {
+                if let Some((_, items)) = &m.content {
+                    definitions(items, out);
+                }
+                continue;
+            },
+            _ => continue,
+        };
+        strip_docs(attrs);
+        StripDocs.visit_item_mut(&mut item);
+        out.entry(name).or_default().push(item.to_token_stream().to_string());
+    }
+}
+
+fn names(tokens: TokenStream, out: &mut Vec<String>){
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => out.push(ident.to_string()),
+            TokenTree::Group(group) => names(group.stream(), out),
+            TokenTree::Punct(_) | TokenTree::Literal(_) => {},
+        }
+    }
+}
+
+/// The names of `definitions` reached from `root`.
+fn reached(definitions: &BTreeMap<String, Vec<String>>, root: &str) -> BTreeSet<String>{
+    let mut seen = BTreeSet::new();
+    let mut queue = vec![root.to_owned()];
+    while let Some(name) = queue.pop() {
+        let Some(texts) = definitions.get(&name) else {
+            continue;
+        };
+        if !seen.insert(name) {
+            continue;
+        }
+        for text in texts {
+            let tokens: TokenStream = text.parse().expect("printed tokens parse again");
+            names(tokens, &mut queue);
+        }
+    }
+    seen
+}
+
+fn main() {
+    let mut sources = Vec::new();
+    files(Path::new("src"), &mut sources);
+    sources.sort();
+
+    let mut defs = BTreeMap::new();
+    for path in &sources {
+        println!("cargo:rerun-if-changed={}", path.display());
+        if path.extension().is_some_and(|e| e == "rs") {
+            let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("could not read {}: {e}", path.display()));
+            // left to the compiler to report
+            if let Ok(file) = syn::parse_file(&text) {
+                definitions(&file.items, &mut defs);
+            }
+        }
+    }
+    println!("cargo:rerun-if-changed=src");
+
+    let mut hash = FNV_OFFSET;
+    for name in reached(&defs, ROOT) {
+        hash = hash_bytes(hash, name.as_bytes());
+        for text in &defs[&name] {
+            hash = hash_bytes(hash, &(text.len() as u64).to_le_bytes());
+            hash = hash_bytes(hash, text.as_bytes());
+        }
+    }
+
+    for path in ["Cargo.toml", "../Cargo.lock"] {
+        println!("cargo:rerun-if-changed={path}");
+        // a missing lock file hashes the same as an empty one
+        let contents = fs::read(path).unwrap_or_default();
+        hash = hash_bytes(hash, path.as_bytes());
+        hash = hash_bytes(hash, &(contents.len() as u64).to_le_bytes());
+        hash = hash_bytes(hash, &contents);
+    }
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let version = Command::new(&rustc)
+        .arg("-vV")
+        .output()
+        .unwrap_or_else(|e| panic!("could not run {rustc}: {e}"))
+        .stdout;
+    hash = hash_bytes(hash, &version);
+    for var in ["TARGET", "PROFILE", "CARGO_ENCODED_RUSTFLAGS"] {
+        hash = hash_bytes(hash, std::env::var(var).unwrap_or_default().as_bytes());
+    }
+
+    let out = PathBuf::from(std::env::var_os("OUT_DIR").expect("cargo sets OUT_DIR"));
+    let code = format!("pub const SOURCE_HASH: u64 = {hash:#018x};\n");
+    fs::write(out.join("abi_hash.rs"), code).expect("could not write the ABI hash");
+}
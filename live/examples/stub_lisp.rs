@@ -0,0 +1,19 @@
+//! A stand-in for an external Lisp, to try the `remote` of `see.conf` with.
+//!
+//! `cargo run -p live --example stub_lisp -- [tcp:<host>:<port> | unix:<path>]`
+
+use live::remote::{self, Address};
+
+const DEFAULT_ADDRESS: &str = "tcp:127.0.0.1:4005";
+
+fn main() {
+    let address = std::env::args().nth(1).unwrap_or_else(|| DEFAULT_ADDRESS.to_owned());
+    let served = Address::parse(&address).and_then(|address| {
+        println!("serving on {address}");
+        remote::serve_stub(&address)
+    });
+    if let Err(e) = served {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,5 @@
+//! Fingerprint of the library shared with the host across reloads.
+
+// `SOURCE_HASH`, a hash of the definitions `State` is made of, the lock file
+// and the compiler, see `build.rs`
+include!(concat!(env!("OUT_DIR"), "/abi_hash.rs"));
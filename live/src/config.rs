@@ -0,0 +1,176 @@
+//! `see.conf`, found next to the other assets.
+//!
+//! Lines of `key = value`, `#` starts a comment:
+//!
+//! ```text
+//! # tried in order for every glyph
+//! fonts = DejaVuSansMono.ttf, NotoSansMath-Regular.ttf, NotoEmoji-Regular.ttf
+//! # an external Lisp to send forms to, or `unix:<path>`
+//! remote = tcp:127.0.0.1:4005
+//! # a unix socket other programs can control the viewer through
+//! control = /tmp/see.sock
+//! # colors of the boxes of lists, by how deeply they are nested, as
+//! # `rrggbb` or `rrggbbaa`
+//! palette = e06c75, e5c07b, 98c379, 56b6c2, 61afef, c678dd
+//! # opacity of the boxes' background in the same colors, 0 for none
+//! fill = 24
+//! ```
+
+use crate::graphics::{Assets, Color};
+
+const CONFIG_FILE: &str = "see.conf";
+
+pub struct Config{
+    pub fonts: Vec<String>,
+    pub remote: Option<String>,
+    pub control: Option<String>,
+    pub palette: Palette,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            fonts: vec!["DejaVuSansMono.ttf".to_owned()],
+            remote: None,
+            control: None,
+            palette: Palette::default(),
+        }
+    }
+}
+
+/// Colors of the boxes of lists, cycling with how deeply they are nested.
+pub struct Palette{
+    outlines: Vec<Color>,
+    fill_alpha: u8,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let outlines = ["e06c75", "e5c07b", "98c379", "56b6c2", "61afef", "c678dd"];
+        Self {
+            outlines: outlines.iter().map(|hex| parse_color(hex).expect("the default palette is valid")).collect(),
+            fill_alpha: 0,
+        }
+    }
+}
+
+impl Palette {
+    /// Outline of a box `depth` lists deep.
+    pub fn outline(&self, depth: usize) -> Color{
+        self.outlines[depth % self.outlines.len()]
+    }
+
+    /// Background of a box `depth` lists deep, if they have one.
+    pub fn fill(&self, depth: usize) -> Option<Color>{
+        (self.fill_alpha != 0).then(|| Color { a: self.fill_alpha, ..self.outline(depth) })
+    }
+}
+
+/// `rrggbb` or `rrggbbaa`.
+fn parse_color(hex: &str) -> Result<Color, String>{
+    let channels: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok()))
+        .collect();
+    match channels.as_deref() {
+        Some(&[r, g, b]) => Ok(Color { r, g, b, a: 255 }),
+        Some(&[r, g, b, a]) => Ok(Color { r, g, b, a }),
+        _ => Err(format!("`{hex}` is not a color, expected `rrggbb` or `rrggbbaa`")),
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String>{
+        let mut config = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{CONFIG_FILE}:{}: expected `key = value`", i + 1))?;
+            let at = |e: String| format!("{CONFIG_FILE}:{}: {e}", i + 1);
+            let list = || value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_owned);
+            match key.trim() {
+                "fonts" => config.fonts = list().collect(),
+                "remote" => config.remote = Some(value.trim().to_owned()),
+                "control" => config.control = Some(value.trim().to_owned()),
+                "palette" => {
+                    let colors = list().map(|hex| parse_color(&hex)).collect::<Result<Vec<_>, _>>().map_err(at)?;
+                    if colors.is_empty() {
+                        return Err(at("the palette needs at least one color".to_owned()));
+                    }
+                    config.palette.outlines = colors;
+                },
+                "fill" => {
+                    config.palette.fill_alpha = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| at(format!("`{}` is not an opacity from 0 to 255", value.trim())))?;
+                },
+                key => return Err(format!("{CONFIG_FILE}:{}: unknown key `{key}`", i + 1)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads the config file, falling back to the defaults if there is none
+    /// or it is broken.
+    pub fn load(assets: &Assets) -> Self{
+        let Ok(path) = assets.resolve(CONFIG_FILE) else {
+            return Self::default();
+        };
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))
+            .and_then(|text| Self::parse(&text));
+        parsed.unwrap_or_else(|e| {
+            eprintln!("{e}, using the default config");
+            Self::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_keys_and_skips_comments(){
+        let config = Config::parse("# fonts\n\nfonts = a.ttf, b.ttf ,, # c.ttf\nremote = tcp:127.0.0.1:4005\ncontrol=/tmp/see.sock\n").unwrap();
+        assert_eq!(config.fonts, ["a.ttf", "b.ttf"]);
+        assert_eq!(config.remote.as_deref(), Some("tcp:127.0.0.1:4005"));
+        assert_eq!(config.control.as_deref(), Some("/tmp/see.sock"));
+    }
+
+    #[test]
+    fn errors_name_the_line(){
+        assert_eq!(Config::parse("fonts = a.ttf\nfonts a.ttf").err().as_deref(), Some("see.conf:2: expected `key = value`"));
+        assert_eq!(Config::parse("# size\nsize = 3").err().as_deref(), Some("see.conf:2: unknown key `size`"));
+    }
+
+    fn rgba(c: Color) -> (u8, u8, u8, u8){
+        (c.r, c.g, c.b, c.a)
+    }
+
+    #[test]
+    fn reads_the_palette(){
+        let config = Config::parse("palette = ff0000, 00ff0080\nfill = 24").unwrap();
+        let palette = &config.palette;
+        assert_eq!(rgba(palette.outline(0)), (255, 0, 0, 255));
+        assert_eq!(rgba(palette.outline(3)), (0, 255, 0, 128));
+        assert_eq!(palette.fill(2).map(rgba), Some((255, 0, 0, 24)));
+        assert!(Config::parse("").unwrap().palette.fill(0).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_colors(){
+        assert_eq!(
+            Config::parse("palette = ff0000, 00gg00").err().as_deref(),
+            Some("see.conf:1: `00gg00` is not a color, expected `rrggbb` or `rrggbbaa`"),
+        );
+        assert_eq!(Config::parse("palette = ff00").err().as_deref(), Some("see.conf:1: `ff00` is not a color, expected `rrggbb` or `rrggbbaa`"));
+        assert_eq!(Config::parse("palette = ,").err().as_deref(), Some("see.conf:1: the palette needs at least one color"));
+        assert_eq!(Config::parse("fill = 300").err().as_deref(), Some("see.conf:1: `300` is not an opacity from 0 to 255"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Lets other programs, like an editor, drive the viewer over a Unix socket.
+//!
+//! Clients send a JSON object per line and get one back for each, either
+//! `{"ok":true}` or `{"ok":false,"error":"..."}`:
+//!
+//! ```text
+//! {"cmd":"open","path":"fizbuz.lisp"}
+//! {"cmd":"focus","line":3,"column":5}
+//! {"cmd":"screenshot","path":"fizbuz.png"}
+//! {"cmd":"show","expr":"(fizbuz 15)"}
+//! {"cmd":"show","expr":["fizbuz",["+",10,5]]}
+//! ```
+//!
+//! `focus` marks the innermost node at a position in the file opened last,
+//! `show` takes Lisp text or a tree of arrays.
+
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+use crate::{Expr, State};
+use crate::graphics::Vec2;
+use crate::json::Json;
+use crate::persist::{self, Persist};
+use crate::reader;
+use crate::source::Source;
+use crate::socket::{LineStream, Stream};
+
+pub enum Command{
+    Open(PathBuf),
+    /// Both start at 1.
+    Focus{ line: usize, column: usize },
+    Screenshot(String),
+    Show(Vec<Expr>),
+}
+
+impl Command {
+    pub fn parse(text: &str) -> Result<Self, String>{
+        let message = Json::parse(text)?;
+        let field = |key: &str| message.get(key).ok_or_else(|| format!("missing `{key}`"));
+        let string = |key: &str| field(key)?.as_str().ok_or_else(|| format!("`{key}` is not a string"));
+        let number = |key: &str| field(key)?.as_usize().ok_or_else(|| format!("`{key}` is not a whole number"));
+        Ok(match string("cmd")? {
+            "open" => Self::Open(string("path")?.into()),
+            "focus" => Self::Focus { line: number("line")?, column: number("column")? },
+            "screenshot" => Self::Screenshot(string("path")?.to_owned()),
+            "show" => Self::Show(match field("expr")? {
+                Json::Str(text) => reader::read_all(text)?,
+                tree => vec![expr(tree)?],
+            }),
+            cmd => return Err(format!("unknown command `{cmd}`")),
+        })
+    }
+}
+
+/// Arrays are lists, strings and numbers atoms.
+fn expr(json: &Json) -> Result<Expr, String>{
+    match json {
+        Json::Array(items) => items.iter().map(expr).collect::<Result<_, _>>().map(Expr::List),
+        Json::Str(atom) if !atom.is_empty() && !atom.contains(char::is_whitespace) => Ok(Expr::Atom(atom.clone())),
+        Json::Str(atom) => Err(format!("`{atom}` is not an atom")),
+        Json::Number(_) | Json::Bool(_) => Ok(Expr::Atom(json.to_string())),
+        Json::Null | Json::Object(_) => Err(format!("{json} is not an expression")),
+    }
+}
+
+fn reply(result: Result<(), String>) -> Json{
+    let mut members = vec![("ok".to_owned(), Json::Bool(result.is_ok()))];
+    if let Err(e) = result {
+        members.push(("error".to_owned(), Json::Str(e)));
+    }
+    Json::Object(members)
+}
+
+pub struct Control{
+    #[cfg(unix)]
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<LineStream>,
+}
+
+impl Control {
+    /// Listens on a socket at `path`, replacing one left behind. Fails if
+    /// something else is at `path`.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> io::Result<Self>{
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is not a socket", path.display()))),
+            Err(_) => {},
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, path: path.to_owned(), clients: Vec::new() })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> io::Result<Self>{
+        Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets are not supported here"))
+    }
+
+    /// Accepts new clients and runs the commands they sent with `run`,
+    /// answering each with its result.
+    pub fn poll(&mut self, mut run: impl FnMut(Command) -> Result<(), String>){
+        #[cfg(unix)]
+        while let Ok((stream, _)) = self.listener.accept() {
+            match LineStream::new(Stream::Unix(stream)) {
+                Ok(client) => self.clients.push(client),
+                Err(e) => eprintln!("could not accept a control client: {e}"),
+            }
+        }
+        self.clients.retain_mut(|client| {
+            let mut lines = Vec::new();
+            let open = client.poll(&mut lines).is_ok();
+            for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+                let result = Command::parse(line).and_then(&mut run);
+                if client.send(&reply(result).to_string()).is_err() {
+                    return false;
+                }
+            }
+            open
+        });
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A node marked with `focus`.
+pub struct Focus{
+    pub path: Vec<usize>,
+    /// `Document::revision` the node was marked at.
+    pub revision: u64,
+    /// Set once the camera moved to the node.
+    pub scrolled: bool,
+}
+
+/// Like `Folds`, a loaded focus is of no revision until it is moved to the
+/// document's.
+impl Persist for Focus {
+    fn save(&self, w: &mut persist::Writer) {
+        self.path.save(w);
+        self.scrolled.save(w);
+    }
+    fn load(r: &mut persist::Reader) -> Option<Self> {
+        Some(Self { path: Vec::load(r)?, revision: u64::MAX, scrolled: bool::load(r)? })
+    }
+}
+
+/// Carries out `command` on the state.
+pub fn run(state: &mut State, command: Command) -> Result<(), String>{
+    let doc = &mut state.session.doc;
+    match command {
+        Command::Open(path) => {
+            state.source = Some(Source::open(path, doc)?);
+            state.session.camera = Vec2::ZERO;
+            state.focus = None;
+        },
+        Command::Focus { line, column } => {
+            let source = state.source
+                .as_ref()
+                .filter(|source| source.revision == doc.revision())
+                .ok_or("no file is open, or it was changed since")?;
+            let path = source
+                .path_at(line, column)
+                .ok_or_else(|| format!("no form at {}:{line}:{column}", source.path.display()))?;
+            state.focus = Some(Focus { path, revision: doc.revision(), scrolled: false });
+            // the marked node is in the document, not in the views replacing it
+            state.stepper = None;
+            state.macro_view = None;
+        },
+        Command::Screenshot(path) => state.g.screenshot(&path)?,
+        Command::Show(forms) => {
+            doc.set_root(Expr::List(forms));
+            state.session.camera = Vec2::ZERO;
+            state.source = None;
+            state.focus = None;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands(){
+        let Ok(Command::Focus { line: 3, column: 5 }) = Command::parse(r#"{"cmd":"focus","line":3,"column":5}"#) else {
+            panic!("expected a focus command");
+        };
+        let Ok(Command::Show(forms)) = Command::parse(r#"{"cmd":"show","expr":["f",["+",10,5]]}"#) else {
+            panic!("expected a show command");
+        };
+        assert_eq!(forms.iter().map(String::from).collect::<Vec<_>>(), ["(f (+ 10 5))"]);
+        assert_eq!(Command::parse(r#"{"cmd":"focus","line":-1,"column":5}"#).err().as_deref(), Some("`line` is not a whole number"));
+        assert_eq!(Command::parse(r#"{"cmd":"jump"}"#).err().as_deref(), Some("unknown command `jump`"));
+        assert_eq!(Command::parse(r#"{"cmd":"show","expr":"a b"}"#).map(|_| ()), Ok(()));
+        assert_eq!(Command::parse(r#"{"cmd":"show","expr":["a b"]}"#).err().as_deref(), Some("`a b` is not an atom"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bind_replaces_only_sockets(){
+        let path = std::env::temp_dir().join(format!("see-control-{}", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(Control::bind(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(&path).unwrap();
+        // a socket left behind by a listener that is gone
+        drop(UnixListener::bind(&path).unwrap());
+        let control = Control::bind(&path).unwrap();
+        drop(control);
+        assert!(!path.exists());
+    }
+}
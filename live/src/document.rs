@@ -0,0 +1,338 @@
+//! The displayed document and the layout retained for it.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::{Expr, Tree};
+use crate::needed_space::NeededSpace;
+use crate::persist::{self, Persist};
+
+/// Layouts of the document's nodes, by path.
+///
+/// A missing entry is a dirty node. Every edit goes through `Document`,
+/// which drops the entries of the nodes it touches along with their
+/// ancestors. Nodes are found by address through an index of the document's
+/// current nodes, so other nodes laid out with the cache, like temporary
+/// ones, are not retained.
+#[derive(Default)]
+pub struct LayoutCache{
+    /// Font generation and text scale the entries were computed with.
+    key: RefCell<(u64, u32)>,
+    entries: RefCell<BTreeMap<Vec<usize>, Rc<Tree<NeededSpace>>>>,
+    /// Path of every node of the document, by address.
+    paths: HashMap<*const Expr, Vec<usize>>,
+}
+
+impl LayoutCache {
+    pub(crate) fn get(&self, expr: &Expr) -> Option<Rc<Tree<NeededSpace>>>{
+        let path = self.paths.get(&(expr as *const _))?;
+        self.entries.borrow().get(path).cloned()
+    }
+    pub(crate) fn insert(&self, expr: &Expr, layout: Rc<Tree<NeededSpace>>){
+        if let Some(path) = self.paths.get(&(expr as *const _)) {
+            self.entries.borrow_mut().insert(path.clone(), layout);
+        }
+    }
+    fn forget(&self, path: &[usize]){
+        self.entries.borrow_mut().remove(path);
+    }
+    /// Forgets the layouts of the node at `path` and the nodes below it.
+    fn forget_subtree(&self, path: &[usize]){
+        let mut entries = self.entries.borrow_mut();
+        // sorted, the paths below `path` follow it
+        let below: Vec<Vec<usize>> = entries
+            .range(path.to_vec()..)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in below {
+            entries.remove(&p);
+        }
+    }
+    /// Moves the layouts of the children of `parent` from index `from` on
+    /// back by one, after the one before them was removed.
+    fn shift_back(&self, parent: &[usize], from: usize){
+        let mut entries = self.entries.borrow_mut();
+        let moved: Vec<Vec<usize>> = entries
+            .range([parent, &[from]].concat()..)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(parent))
+            .cloned()
+            .collect();
+        // in order, each one's new place was just vacated
+        for mut p in moved {
+            let layout = entries.remove(&p).unwrap();
+            p[parent.len()] -= 1;
+            entries.insert(p, layout);
+        }
+    }
+    /// Indexes the children of the list at `parent` again after it changed
+    /// length, which may move them but not the nodes below them, and the
+    /// whole subtrees of the ones from index `from` on, whose paths changed.
+    fn reindex_children(&mut self, children: &[Expr], parent: &[usize], from: usize){
+        let mut path = parent.to_vec();
+        for (i, child) in children.iter().enumerate() {
+            path.push(i);
+            if i < from {
+                self.paths.insert(child, path.clone());
+            } else {
+                self.index(child, &mut path);
+            }
+            path.pop();
+        }
+    }
+    /// Adds `node`, at `path`, and the nodes below it to the index.
+    fn index(&mut self, node: &Expr, path: &mut Vec<usize>){
+        self.paths.insert(node, path.clone());
+        if let Expr::List(children) = node {
+            for (i, child) in children.iter().enumerate() {
+                path.push(i);
+                self.index(child, path);
+                path.pop();
+            }
+        }
+    }
+    /// Removes `node` and the nodes below it from the index.
+    fn unindex(&mut self, node: &Expr){
+        self.paths.remove(&(node as *const _));
+        if let Expr::List(children) = node {
+            children.iter().for_each(|c| self.unindex(c));
+        }
+    }
+    /// Drops everything if the fonts or the text scale changed since the
+    /// entries were computed.
+    pub(crate) fn reset_if_changed(&self, font_generation: u64, text_scale: f32){
+        let key = (font_generation, text_scale.to_bits());
+        if *self.key.borrow() != key {
+            *self.key.borrow_mut() = key;
+            self.entries.borrow_mut().clear();
+        }
+    }
+}
+
+pub struct Document{
+    /// Boxed so that moving the document keeps the root's address.
+    root: Box<Expr>,
+    pub(crate) layout: LayoutCache,
+    /// Bumped by every edit.
+    revision: u64,
+}
+
+impl Document {
+    pub fn new(root: Expr) -> Self{
+        let mut doc = Self { root: Box::new(root), layout: LayoutCache::default(), revision: 0 };
+        doc.layout.index(&doc.root, &mut Vec::new());
+        doc
+    }
+
+    pub fn root(&self) -> &Expr{
+        &self.root
+    }
+
+    /// Changes whenever the document is edited.
+    pub fn revision(&self) -> u64{
+        self.revision
+    }
+
+    /// The top-level forms, the children of the root.
+    pub fn forms(&self) -> &[Expr]{
+        self.root.forms()
+    }
+
+    /// The node reached by taking the `i`th child for each `i` in `path`.
+    pub fn get(&self, path: &[usize]) -> Option<&Expr>{
+        self.root.at(path)
+    }
+
+    /// The path of the node at address `node`, the inverse of `get`.
+    pub fn path_of(&self, node: *const Expr) -> Option<Vec<usize>>{
+        self.layout.paths.get(&node).cloned()
+    }
+
+    /// Marks the node at `path` and its ancestors for layout, for when the
+    /// way it is rendered changed.
+    pub fn mark_dirty(&self, path: &[usize]){
+        for len in 0..=path.len() {
+            self.layout.forget(&path[..len]);
+        }
+    }
+
+    /// Drops every retained layout, for when the way many nodes are rendered
+    /// changed.
+    pub fn relayout(&self){
+        self.layout.entries.borrow_mut().clear();
+    }
+
+    /// Replaces the node at `path`, returning the old one.
+    ///
+    /// Only the new node and its ancestors are laid out again.
+    pub fn replace(&mut self, path: &[usize], new: Expr) -> Option<Expr>{
+        self.get(path)?;
+        self.mark_dirty(path);
+        self.layout.forget_subtree(path);
+        let node = node_mut(&mut self.root, path)?;
+        // the old nodes' addresses are free to be reused by the new ones
+        self.layout.unindex(node);
+        let old = std::mem::replace(node, new);
+        self.layout.index(node, &mut path.to_vec());
+        self.revision += 1;
+        Some(old)
+    }
+
+    /// Adds `new` as the last child of the list at `parent`, false if there
+    /// is none.
+    ///
+    /// Only the new node and its ancestors are laid out again.
+    pub fn push(&mut self, parent: &[usize], new: Expr) -> bool{
+        let Some(Expr::List(children)) = node_mut(&mut self.root, parent) else {
+            return false;
+        };
+        for child in children.iter() {
+            self.layout.paths.remove(&(child as *const _));
+        }
+        children.push(new);
+        self.layout.reindex_children(children, parent, children.len() - 1);
+        self.mark_dirty(parent);
+        self.revision += 1;
+        true
+    }
+
+    /// Takes the node at `path` out of its list, returning it.
+    ///
+    /// The nodes after it move up and keep their layouts, only its ancestors
+    /// are laid out again.
+    pub fn remove(&mut self, path: &[usize]) -> Option<Expr>{
+        let (&i, parent) = path.split_last()?;
+        let Some(Expr::List(children)) = node_mut(&mut self.root, parent) else {
+            return None;
+        };
+        let removed = children.get(i)?;
+        self.layout.unindex(removed);
+        for child in children.iter() {
+            self.layout.paths.remove(&(child as *const _));
+        }
+        let old = children.remove(i);
+        self.layout.reindex_children(children, parent, i);
+        self.layout.forget_subtree(path);
+        self.layout.shift_back(parent, i + 1);
+        self.mark_dirty(parent);
+        self.revision += 1;
+        Some(old)
+    }
+
+    /// Replaces the whole document.
+    pub fn set_root(&mut self, root: Expr){
+        self.layout.entries.borrow_mut().clear();
+        self.layout.paths.clear();
+        self.revision += 1;
+        *self.root = root;
+        self.layout.index(&self.root, &mut Vec::new());
+    }
+}
+
+/// The node at `path` below `node`, for changing it.
+fn node_mut<'a>(node: &'a mut Expr, path: &[usize]) -> Option<&'a mut Expr>{
+    path.iter().try_fold(node, |node, &i| match node {
+        Expr::List(children) => children.get_mut(i),
+        Expr::Atom(_) => None,
+    })
+}
+
+impl Persist for Document {
+    fn save(&self, w: &mut persist::Writer) {
+        self.root.save(w);
+    }
+    fn load(r: &mut persist::Reader) -> Option<Self> {
+        Some(Self::new(Expr::load(r)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(text: &str) -> Expr{
+        Expr::Atom(text.to_owned())
+    }
+
+    /// `(a (b c) d)`
+    fn doc() -> Document{
+        Document::new(Expr::List(vec![atom("a"), Expr::List(vec![atom("b"), atom("c")]), atom("d")]))
+    }
+
+    fn layout() -> Rc<Tree<NeededSpace>>{
+        Rc::new(Tree::leaf(NeededSpace::ZERO))
+    }
+
+    fn cached(doc: &Document, path: &[usize]) -> bool{
+        doc.layout.get(doc.get(path).unwrap()).is_some()
+    }
+
+    #[test]
+    fn replacing_drops_the_node_and_its_ancestors(){
+        let mut doc = doc();
+        for path in [&[][..], &[0], &[1], &[1, 0], &[1, 1], &[2]] {
+            doc.layout.insert(doc.get(path).unwrap(), layout());
+        }
+        doc.replace(&[1, 0], Expr::List(vec![atom("e")]));
+        for (path, kept) in [(&[][..], false), (&[0], true), (&[1], false), (&[1, 0], false), (&[1, 1], true), (&[2], true)] {
+            assert_eq!(cached(&doc, path), kept, "{path:?}");
+        }
+        doc.layout.insert(doc.get(&[1, 0, 0]).unwrap(), layout());
+        doc.replace(&[1], atom("f"));
+        assert_eq!(doc.layout.entries.borrow().keys().cloned().collect::<Vec<_>>(), vec![vec![0], vec![2]]);
+    }
+
+    #[test]
+    fn nodes_outside_the_document_are_not_kept(){
+        let doc = doc();
+        let outside = atom("a");
+        doc.layout.insert(&outside, layout());
+        assert!(doc.layout.get(&outside).is_none());
+        assert!(doc.layout.entries.borrow().is_empty());
+    }
+
+    #[test]
+    fn paths_follow_edits(){
+        // every node of the document is indexed at its path, and nothing else
+        fn check(doc: &Document, nodes: usize){
+            assert_eq!(doc.layout.paths.len(), nodes);
+            for (&node, path) in &doc.layout.paths {
+                assert!(std::ptr::eq(doc.get(path).unwrap(), node), "{path:?}");
+            }
+        }
+        let mut doc = doc();
+        check(&doc, 6);
+        doc.replace(&[1], Expr::List(vec![atom("x"), Expr::List(vec![atom("y")])]));
+        check(&doc, 7);
+        assert_eq!(doc.path_of(doc.get(&[1, 1, 0]).unwrap()), Some(vec![1, 1, 0]));
+        assert!(doc.push(&[1], atom("w")));
+        check(&doc, 8);
+        assert!(!doc.push(&[0], atom("v")));
+        assert_eq!(doc.remove(&[0]).map(|old| String::from(&old)).as_deref(), Some("a"));
+        check(&doc, 7);
+        assert_eq!(doc.path_of(doc.get(&[0, 1, 0]).unwrap()), Some(vec![0, 1, 0]));
+        doc.set_root(atom("z"));
+        check(&doc, 1);
+    }
+
+    #[test]
+    fn pushing_and_removing_keep_the_siblings_layouts(){
+        let mut doc = doc();
+        for path in [&[][..], &[0], &[1], &[1, 0], &[1, 1], &[2]] {
+            doc.layout.insert(doc.get(path).unwrap(), layout());
+        }
+        doc.push(&[], atom("e"));
+        assert_eq!(doc.layout.entries.borrow().keys().cloned().collect::<Vec<_>>(), [vec![0], vec![1], vec![1, 0], vec![1, 1], vec![2]]);
+        doc.layout.insert(doc.get(&[3]).unwrap(), layout());
+        doc.remove(&[0]);
+        // `(b c)`, `d` and `e` moved up with their layouts
+        assert_eq!(doc.layout.entries.borrow().keys().cloned().collect::<Vec<_>>(), [vec![0], vec![0, 0], vec![0, 1], vec![1], vec![2]]);
+        for path in [&[0][..], &[0, 0], &[0, 1], &[1], &[2]] {
+            assert!(cached(&doc, path), "{path:?}");
+        }
+        assert_eq!(String::from(doc.root()), "((b c) d e)");
+    }
+}
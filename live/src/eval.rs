@@ -0,0 +1,1185 @@
+//! Interpreter for the displayed code.
+//!
+//! A small Lisp evaluated straight from `Expr`: `define`/`defun`, `lambda`,
+//! `if`, `let`, `while`, arithmetic and `print`. `nil` is the only false value
+//! and the empty list.
+//!
+//! Macros are defined with `defmacro`, whose body builds the expansion, or
+//! with `define-syntax` and `syntax-rules`. A subset of `loop` is built in as
+//! a macro over `let` and `while`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use syntax_rules::SyntaxRules;
+
+use crate::Expr;
+use crate::document::Document;
+use crate::reader;
+
+mod syntax_rules;
+
+/// Evaluation steps a top-level form may take before it is stopped, so that an
+/// endless loop doesn't hang the editor.
+const FUEL: u64 = 1_000_000;
+/// Deepest nesting of lists being evaluated and calls, past it the native
+/// stack would overflow.
+const MAX_DEPTH: usize = 500;
+/// Lines of output kept, older ones are dropped.
+const MAX_OUTPUT: usize = 1000;
+/// Expansions of a single form before it is assumed to expand forever.
+const MAX_EXPANSIONS: usize = 1000;
+
+#[derive(Clone)]
+pub enum Value{
+    Nil,
+    True,
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    Symbol(Rc<str>),
+    /// A non-empty list, the empty list is `Nil`.
+    List(Rc<[Value]>),
+    Lambda(Rc<Lambda>),
+    Builtin(&'static str, Builtin),
+    Macro(Rc<Macro>),
+}
+
+type Builtin = fn(&mut Interpreter, &[Value]) -> Result<Value, String>;
+/// Turns a macro call into its expansion.
+type Expander = fn(&Expr) -> Result<Expansion, String>;
+
+/// The nodes of an expansion copied from the macro call, each as its path in
+/// the expansion and the path in the call of the node it copies. The nodes
+/// below a copy aren't listed, they copy the ones below its original.
+pub type Origins = Vec<(Vec<usize>, Vec<usize>)>;
+
+/// A macro call rewritten.
+pub struct Expansion{
+    pub form: Expr,
+    /// Where the parts of `form` taken from the call came from.
+    pub origins: Origins,
+}
+
+pub struct Lambda{
+    name: Option<String>,
+    params: Vec<String>,
+    /// Takes the arguments past `params` as a list, after `&rest` or `&body`.
+    rest: Option<String>,
+    body: Rc<[Expr]>,
+    env: Rc<Env>,
+}
+
+pub enum Macro{
+    /// Called with the unevaluated arguments, returns the expansion.
+    Procedure(Rc<Lambda>),
+    Rules(String, SyntaxRules),
+    /// Expanded by a native function, see `MACROS`.
+    Builtin(&'static str, Expander),
+}
+
+impl Value {
+    fn is_true(&self) -> bool{
+        !matches!(self, Value::Nil)
+    }
+
+    fn bool(b: bool) -> Self{
+        if b { Value::True } else { Value::Nil }
+    }
+
+    fn list(items: Vec<Value>) -> Self{
+        if items.is_empty() { Value::Nil } else { Value::List(items.into()) }
+    }
+
+    /// The value of a literal atom, `None` for symbols.
+    fn literal(atom: &str) -> Option<Self>{
+        if let Ok(i) = atom.parse() {
+            return Some(Value::Int(i));
+        }
+        // keeps names like `inf` and `nan` symbols
+        let digits = atom.trim_start_matches(['-', '+']).trim_start_matches('.');
+        if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            if let Ok(f) = atom.parse() {
+                return Some(Value::Float(f));
+            }
+        }
+        if let Some(s) = reader::string_literal(atom) {
+            return Some(Value::Str(s.into()));
+        }
+        match atom {
+            "nil" | "#f" => Some(Value::Nil),
+            "t" | "#t" => Some(Value::True),
+            _ => None,
+        }
+    }
+
+    /// `expr` as data, as with `quote`.
+    fn quoted(expr: &Expr) -> Self{
+        match expr {
+            Expr::Atom(atom) => Value::literal(atom).unwrap_or_else(|| Value::Symbol(atom.as_str().into())),
+            Expr::List(children) => Value::list(children.iter().map(Value::quoted).collect()),
+        }
+    }
+
+    /// The allocation of a symbol, string or list, which tells it apart
+    /// from equal values made elsewhere.
+    fn identity(&self) -> Option<*const ()>{
+        match self {
+            Value::Symbol(s) | Value::Str(s) => Some(Rc::as_ptr(s) as *const ()),
+            Value::List(items) => Some(Rc::as_ptr(items) as *const ()),
+            _ => None,
+        }
+    }
+
+    /// The value as it would be read back.
+    pub fn to_expr(&self) -> Expr{
+        match self {
+            Value::List(items) => Expr::List(items.iter().map(Value::to_expr).collect()),
+            v => Expr::Atom(v.to_string()),
+        }
+    }
+
+    /// Text written by `print`, strings without their quotes.
+    pub fn display(&self) -> String{
+        match self {
+            Value::Str(s) => s.to_string(),
+            v => v.to_string(),
+        }
+    }
+}
+
+/// Formats values the way they are read back.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::True => write!(f, "t"),
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::Str(s) => write!(f, "{s:?}"),
+            Value::Symbol(s) => write!(f, "{s}"),
+            Value::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, ")")
+            },
+            Value::Lambda(l) => match &l.name {
+                Some(name) => write!(f, "#<function {name}>"),
+                None => write!(f, "#<lambda>"),
+            },
+            Value::Builtin(name, _) => write!(f, "#<builtin {name}>"),
+            Value::Macro(m) => match &**m {
+                Macro::Procedure(l) => write!(f, "#<macro {}>", l.name.as_deref().unwrap_or("?")),
+                Macro::Rules(name, _) => write!(f, "#<macro {name}>"),
+                Macro::Builtin(name, _) => write!(f, "#<macro {name}>"),
+            },
+        }
+    }
+}
+
+pub struct Env{
+    vars: RefCell<HashMap<String, Value>>,
+    parent: Option<Rc<Env>>,
+}
+
+impl Env {
+    fn new(parent: Option<Rc<Env>>) -> Rc<Self>{
+        Rc::new(Self { vars: RefCell::default(), parent })
+    }
+
+    fn get(&self, name: &str) -> Option<Value>{
+        match self.vars.borrow().get(name) {
+            Some(v) => Some(v.clone()),
+            None => self.parent.as_ref()?.get(name),
+        }
+    }
+
+    fn define(&self, name: &str, value: Value){
+        self.vars.borrow_mut().insert(name.to_owned(), value);
+    }
+
+    /// Assigns to the innermost existing binding of `name`.
+    fn set(&self, name: &str, value: Value) -> Result<(), String>{
+        if let Some(slot) = self.vars.borrow_mut().get_mut(name) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.parent {
+            Some(parent) => parent.set(name, value),
+            None => Err(format!("`{name}` is not defined")),
+        }
+    }
+}
+
+/// Steps recorded past this are dropped.
+const MAX_STEPS: usize = 100_000;
+
+/// One evaluation of a node, as recorded by a `Trace`.
+pub struct Step{
+    /// Path of the node from the root the trace was made for.
+    pub path: Vec<usize>,
+    /// `None` when the node is about to be evaluated.
+    pub value: Option<Value>,
+}
+
+/// The evaluations of the nodes of a document, in order.
+///
+/// Literals are left out. Functions defined in the document are followed
+/// into their bodies, whose nodes are recorded at the paths they were
+/// defined at.
+pub struct Trace{
+    paths: HashMap<*const Expr, Vec<usize>>,
+    /// Copied bodies of traced functions, kept so that their addresses in
+    /// `paths` aren't reused.
+    bodies: Vec<Rc<[Expr]>>,
+    pub steps: Vec<Step>,
+    /// Set once steps were dropped for exceeding `MAX_STEPS`.
+    pub truncated: bool,
+}
+
+impl Trace {
+    /// A trace of the nodes below `root`.
+    pub fn new(root: &Expr) -> Self{
+        let mut trace = Self { paths: HashMap::new(), bodies: Vec::new(), steps: Vec::new(), truncated: false };
+        trace.map(root, &mut Vec::new());
+        trace
+    }
+
+    fn map(&mut self, node: &Expr, path: &mut Vec<usize>){
+        self.paths.insert(node, path.clone());
+        if let Expr::List(children) = node {
+            for (i, child) in children.iter().enumerate() {
+                path.push(i);
+                self.map(child, path);
+                path.pop();
+            }
+        }
+    }
+
+    /// Records the nodes of `copy` at the paths of the ones of `original`.
+    fn alias(&mut self, original: &Expr, copy: &Expr){
+        if let Some(path) = self.paths.get(&(original as *const _)).cloned() {
+            self.paths.insert(copy, path);
+        }
+        if let (Expr::List(original), Expr::List(copy)) = (original, copy) {
+            original.iter().zip(copy).for_each(|(o, c)| self.alias(o, c));
+        }
+    }
+
+    fn record(&mut self, path: Vec<usize>, value: Option<Value>){
+        if self.steps.len() < MAX_STEPS {
+            self.steps.push(Step { path, value });
+        } else {
+            self.truncated = true;
+        }
+    }
+}
+
+/// Evaluates forms in a global environment that persists between them.
+pub struct Interpreter{
+    global: Rc<Env>,
+    /// Lines written by `print`.
+    pub output: Vec<String>,
+    fuel: u64,
+    depth: usize,
+    /// Records the evaluations if set.
+    pub trace: Option<Trace>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self{
+        let global = Env::new(None);
+        for &(name, f) in BUILTINS {
+            global.define(name, Value::Builtin(name, f));
+        }
+        for &(name, f) in MACROS {
+            global.define(name, Value::Macro(Rc::new(Macro::Builtin(name, f))));
+        }
+        Self { global, output: Vec::new(), fuel: FUEL, depth: 0, trace: None }
+    }
+
+    /// Evaluates a top-level form.
+    pub fn eval_top(&mut self, expr: &Expr) -> Result<Value, String>{
+        self.fuel = FUEL;
+        self.depth = 0;
+        let global = self.global.clone();
+        self.eval(expr, &global)
+    }
+
+    fn print(&mut self, line: String){
+        self.output.push(line);
+        if self.output.len() > MAX_OUTPUT {
+            self.output.drain(..self.output.len() - MAX_OUTPUT);
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, env: &Rc<Env>) -> Result<Value, String>{
+        let path = match (&self.trace, expr) {
+            (_, Expr::Atom(atom)) if Value::literal(atom).is_some() => None,
+            (Some(trace), expr) => trace.paths.get(&(expr as *const _)).cloned(),
+            (None, _) => None,
+        };
+        let Some(path) = path else {
+            return self.eval_untraced(expr, env);
+        };
+        let trace = |interp: &mut Self, value| interp.trace.as_mut().unwrap().record(path.clone(), value);
+        if let Expr::List(_) = expr {
+            trace(self, None);
+        }
+        let result = self.eval_untraced(expr, env);
+        if let Ok(value) = &result {
+            trace(self, Some(value.clone()));
+        }
+        result
+    }
+
+    /// Lets the trace follow `f` into its body, a copy of `body`.
+    fn trace_lambda(&mut self, body: &[Expr], f: &Value){
+        let (Some(trace), Value::Lambda(l)) = (&mut self.trace, f) else {
+            return;
+        };
+        body.iter().zip(l.body.iter()).for_each(|(o, c)| trace.alias(o, c));
+        trace.bodies.push(l.body.clone());
+    }
+
+    /// Lets the trace follow the macro call `call` into its expansion, where
+    /// the copies of its arguments are recorded at their paths.
+    fn trace_expansion(&mut self, call: &Expr, expansion: Expansion) -> Rc<[Expr]>{
+        let form: Rc<[Expr]> = Rc::new([expansion.form]);
+        if let Some(trace) = &mut self.trace {
+            for (copy, original) in &expansion.origins {
+                if let (Some(copy), Some(original)) = (form[0].at(copy), call.at(original)) {
+                    trace.alias(original, copy);
+                }
+            }
+            trace.bodies.push(form.clone());
+        }
+        form
+    }
+
+    fn eval_untraced(&mut self, expr: &Expr, env: &Rc<Env>) -> Result<Value, String>{
+        if self.fuel == 0 {
+            return Err("evaluation took too many steps".to_owned());
+        }
+        self.fuel -= 1;
+        let items = match expr {
+            Expr::Atom(atom) => {
+                return match Value::literal(atom) {
+                    Some(v) => Ok(v),
+                    None => env.get(atom).ok_or_else(|| format!("`{atom}` is not defined")),
+                };
+            },
+            Expr::List(items) => items,
+        };
+        self.nested(|interp| interp.eval_list(expr, items, env))
+    }
+
+    /// `eval_untraced` of the list `expr`, whose elements are `items`.
+    fn eval_list(&mut self, expr: &Expr, items: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+        let Some((head, args)) = items.split_first() else {
+            return Ok(Value::Nil);
+        };
+        if let Expr::Atom(head) = head {
+            if let Some(special) = self.special_form(head, args, env) {
+                return special;
+            }
+            if let Some(Value::Macro(m)) = env.get(head) {
+                let expansion = self.expand_with(&m, expr)?;
+                let expansion = self.trace_expansion(expr, expansion);
+                return self.eval(&expansion[0], env);
+            }
+        }
+        let f = self.eval(head, env)?;
+        let args = args.iter().map(|arg| self.eval(arg, env)).collect::<Result<Vec<_>, _>>()?;
+        self.apply(&f, &args)
+    }
+
+    fn eval_body(&mut self, body: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+        let mut value = Value::Nil;
+        for expr in body {
+            value = self.eval(expr, env)?;
+        }
+        Ok(value)
+    }
+
+    pub fn apply(&mut self, f: &Value, args: &[Value]) -> Result<Value, String>{
+        match f {
+            Value::Builtin(_, f) => f(self, args),
+            Value::Lambda(l) => {
+                let arity_ok = match l.rest {
+                    Some(_) => args.len() >= l.params.len(),
+                    None => args.len() == l.params.len(),
+                };
+                if !arity_ok {
+                    let at_least = if l.rest.is_some() { "at least " } else { "" };
+                    return Err(format!("{f} takes {at_least}{} arguments, got {}", l.params.len(), args.len()));
+                }
+                let env = Env::new(Some(l.env.clone()));
+                for (param, arg) in l.params.iter().zip(args) {
+                    env.define(param, arg.clone());
+                }
+                if let Some(rest) = &l.rest {
+                    env.define(rest, Value::list(args[l.params.len()..].to_vec()));
+                }
+                self.nested(|interp| interp.eval_body(&l.body, &env))
+            },
+            f => Err(format!("{f} is not a function")),
+        }
+    }
+
+    /// Runs `f` one call deeper.
+    fn nested(&mut self, f: impl FnOnce(&mut Self) -> Result<Value, String>) -> Result<Value, String>{
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("evaluation nested deeper than {MAX_DEPTH}"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Evaluates `head` as a special form, `None` if it is not one.
+    fn special_form(&mut self, head: &str, args: &[Expr], env: &Rc<Env>) -> Option<Result<Value, String>>{
+        Some(match head {
+            "quote" => match args {
+                [x] => Ok(Value::quoted(x)),
+                _ => Err("`quote` takes one argument".to_owned()),
+            },
+            "quasiquote" => match args {
+                [x] => self.quasiquote(x, env),
+                _ => Err("`quasiquote` takes one argument".to_owned()),
+            },
+            "defmacro" => match args {
+                [name, params, body @ ..] => symbol(name).and_then(|name| {
+                    let Value::Lambda(l) = lambda(Some(name), params, body, env)? else {
+                        unreachable!()
+                    };
+                    env.define(name, Value::Macro(Rc::new(Macro::Procedure(l))));
+                    Ok(Value::Symbol(name.into()))
+                }),
+                _ => Err("expected `(defmacro name (params...) body...)`".to_owned()),
+            },
+            "define-syntax" => match args {
+                [name, rules] => symbol(name).and_then(|name| {
+                    let rules = SyntaxRules::parse(rules)?;
+                    env.define(name, Value::Macro(Rc::new(Macro::Rules(name.to_owned(), rules))));
+                    Ok(Value::Symbol(name.into()))
+                }),
+                _ => Err("expected `(define-syntax name (syntax-rules ...))`".to_owned()),
+            },
+            "if" => self.eval_if(args, env),
+            "define" => self.eval_define(args, env),
+            "defun" => match args {
+                [name, params, body @ ..] => symbol(name)
+                    .and_then(|name| {
+                        let f = lambda(Some(name), params, body, env)?;
+                        self.trace_lambda(body, &f);
+                        env.define(name, f);
+                        Ok(Value::Symbol(name.into()))
+                    }),
+                _ => Err("expected `(defun name (params...) body...)`".to_owned()),
+            },
+            "lambda" => match args {
+                [params, body @ ..] => lambda(None, params, body, env).inspect(|f| self.trace_lambda(body, f)),
+                _ => Err("expected `(lambda (params...) body...)`".to_owned()),
+            },
+            "let" => self.eval_let(args, env),
+            "progn" | "begin" => self.eval_body(args, env),
+            "set!" | "setq" => match args {
+                [name, value] => symbol(name).and_then(|name| {
+                    let value = self.eval(value, env)?;
+                    env.set(name, value.clone())?;
+                    Ok(value)
+                }),
+                _ => Err(format!("expected `({head} name value)`")),
+            },
+            "and" => {
+                let mut value = Value::True;
+                for arg in args {
+                    match self.eval(arg, env) {
+                        Ok(v) if v.is_true() => value = v,
+                        result => return Some(result),
+                    }
+                }
+                Ok(value)
+            },
+            "or" => {
+                for arg in args {
+                    match self.eval(arg, env) {
+                        Ok(v) if !v.is_true() => {},
+                        result => return Some(result),
+                    }
+                }
+                Ok(Value::Nil)
+            },
+            "while" => match args {
+                [cond, body @ ..] => self.eval_while(cond, body, env),
+                _ => Err("expected `(while cond body...)`".to_owned()),
+            },
+            _ => return None,
+        })
+    }
+
+    /// The template `x` with the `unquote`d parts evaluated and the
+    /// `unquote-splicing`d ones spliced in.
+    fn quasiquote(&mut self, x: &Expr, env: &Rc<Env>) -> Result<Value, String>{
+        let Expr::List(items) = x else {
+            return Ok(Value::quoted(x));
+        };
+        match items.as_slice() {
+            [Expr::Atom(head), x] if head == "unquote" => return self.eval(x, env),
+            [Expr::Atom(head), _] if head == "unquote-splicing" => {
+                return Err("`,@` outside of a list".to_owned());
+            },
+            _ => {},
+        }
+        let mut out = Vec::new();
+        for item in items {
+            match item {
+                Expr::List(splice) if splice.len() == 2 && matches!(&splice[0], Expr::Atom(h) if h == "unquote-splicing") => {
+                    match self.eval(&splice[1], env)? {
+                        Value::Nil => {},
+                        Value::List(spliced) => out.extend(spliced.iter().cloned()),
+                        v => return Err(format!("`,@` of {v}, which is not a list")),
+                    }
+                },
+                item => out.push(self.quasiquote(item, env)?),
+            }
+        }
+        Ok(Value::list(out))
+    }
+
+    /// The expansion of `form` by `m`.
+    ///
+    /// A `defmacro` is told to have copied a part of the call when its
+    /// expansion holds the very value it was passed, so numbers and `nil`,
+    /// which are not told apart from equal ones, are never linked to the call.
+    fn expand_with(&mut self, m: &Macro, form: &Expr) -> Result<Expansion, String>{
+        match m {
+            Macro::Procedure(l) => {
+                let args: Vec<_> = form.forms().iter().skip(1).map(Value::quoted).collect();
+                let mut arguments = HashMap::new();
+                for (i, arg) in args.iter().enumerate() {
+                    index_values(arg, &mut vec![i + 1], &mut arguments);
+                }
+                let value = self.apply(&Value::Lambda(l.clone()), &args)?;
+                let mut origins = Vec::new();
+                value_origins(&value, &arguments, &mut Vec::new(), &mut origins);
+                Ok(Expansion { form: value.to_expr(), origins })
+            },
+            Macro::Rules(_, rules) => rules.expand(form),
+            Macro::Builtin(_, f) => f(form),
+        }
+    }
+
+    /// Expands `form` once if it is a macro call, `None` if it is not one.
+    pub fn expand_1(&mut self, form: &Expr) -> Result<Option<Expansion>, String>{
+        let Some(Expr::Atom(head)) = form.car() else {
+            return Ok(None);
+        };
+        let Some(Value::Macro(m)) = self.global.get(head) else {
+            return Ok(None);
+        };
+        self.fuel = FUEL;
+        self.depth = 0;
+        self.expand_with(&m, form).map(Some)
+    }
+
+    /// Expands `form` until it is no macro call, then its subforms the same
+    /// way. Quoted forms are left alone.
+    ///
+    /// The origins are followed through every step back to `form`.
+    pub fn expand_all(&mut self, form: &Expr) -> Result<Expansion, String>{
+        let mut expansion = Expansion { form: form.clone(), origins: vec![(vec![], vec![])] };
+        let mut expansions = 0;
+        while let Some(step) = self.expand_1(&expansion.form)? {
+            expansions += 1;
+            if expansions > MAX_EXPANSIONS {
+                return Err(format!("{} keeps expanding", String::from(&expansion.form)));
+            }
+            expansion = Expansion { origins: follow(&expansion.origins, &step.origins), form: step.form };
+        }
+        let items = match expansion.form {
+            Expr::List(items) if !matches!(items.first(), Some(Expr::Atom(h)) if h == "quote") => items,
+            _ => return Ok(expansion),
+        };
+        let mut out = Vec::with_capacity(items.len());
+        let mut origins = Vec::new();
+        let mut unchanged = expansions == 0;
+        for (i, item) in items.iter().enumerate() {
+            let inner = self.expand_all(item)?;
+            unchanged &= inner.origins == [(vec![], vec![])];
+            // the origins of `item` rather than of the whole list
+            let outer: Origins = expansion.origins.iter().filter_map(|(copy, original)| match copy.split_first() {
+                Some((&j, below)) => (j == i).then(|| (below.to_vec(), original.clone())),
+                None => Some((vec![], [original.as_slice(), &[i]].concat())),
+            }).collect();
+            origins.extend(follow(&outer, &inner.origins).into_iter().map(|(copy, original)| ([&[i], copy.as_slice()].concat(), original)));
+            out.push(inner.form);
+        }
+        if unchanged {
+            // a copy of `form` as a whole rather than of each of its parts
+            return Ok(Expansion { form: Expr::List(out), origins: expansion.origins });
+        }
+        Ok(Expansion { form: Expr::List(out), origins })
+    }
+
+    /// Evaluates only the top-level `defmacro` and `define-syntax` forms of
+    /// `forms`, so that their macros can be expanded without running the rest.
+    pub fn define_macros(&mut self, forms: &[Expr]) -> Result<(), String>{
+        for form in forms {
+            if matches!(form.car(), Some(Expr::Atom(h)) if h == "defmacro" || h == "define-syntax") {
+                self.eval_top(form)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_if(&mut self, args: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+        let (cond, then, otherwise) = match args {
+            [cond, then] => (cond, then, None),
+            [cond, then, otherwise] => (cond, then, Some(otherwise)),
+            _ => return Err("expected `(if cond then [else])`".to_owned()),
+        };
+        if self.eval(cond, env)?.is_true() {
+            self.eval(then, env)
+        } else {
+            otherwise.map_or(Ok(Value::Nil), |e| self.eval(e, env))
+        }
+    }
+
+    fn eval_define(&mut self, args: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+        match args {
+            // (define (name params...) body...)
+            [Expr::List(signature), body @ ..] => {
+                let Some((name, params)) = signature.split_first() else {
+                    return Err("`define` of a function without a name".to_owned());
+                };
+                let name = symbol(name)?;
+                let f = lambda_from(Some(name), params, body, env)?;
+                self.trace_lambda(body, &f);
+                env.define(name, f);
+                Ok(Value::Symbol(name.into()))
+            },
+            [name, value] => {
+                let name = symbol(name)?;
+                let value = self.eval(value, env)?;
+                env.define(name, value);
+                Ok(Value::Symbol(name.into()))
+            },
+            _ => Err("expected `(define name value)` or `(define (name params...) body...)`".to_owned()),
+        }
+    }
+
+    fn eval_let(&mut self, args: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+        let Some((Expr::List(bindings), body)) = args.split_first() else {
+            return Err("expected `(let ((name value)...) body...)`".to_owned());
+        };
+        let inner = Env::new(Some(env.clone()));
+        for binding in bindings {
+            match binding {
+                Expr::List(pair) if pair.len() == 2 => {
+                    let value = self.eval(&pair[1], env)?;
+                    inner.define(symbol(&pair[0])?, value);
+                },
+                Expr::Atom(name) => inner.define(name, Value::Nil),
+                _ => return Err(format!("malformed `let` binding {}", String::from(binding))),
+            }
+        }
+        self.eval_body(body, &inner)
+    }
+
+    /// Evaluates `body` for as long as `cond` is true.
+    fn eval_while(&mut self, cond: &Expr, body: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+        while self.eval(cond, env)?.is_true() {
+            self.eval_body(body, env)?;
+        }
+        Ok(Value::Nil)
+    }
+}
+
+/// An evaluation of every top-level form of a document, in order.
+pub struct Run{
+    pub interpreter: Interpreter,
+    /// Result of each top-level form, by address.
+    results: HashMap<*const Expr, Result<Value, String>>,
+    /// `Document::revision` the forms were evaluated at.
+    pub revision: u64,
+}
+
+impl Run {
+    /// Evaluates the forms of `doc` with a fresh interpreter.
+    ///
+    /// A form that fails doesn't stop the ones after it, its error is printed
+    /// and kept as its result.
+    pub fn new(doc: &Document) -> Self{
+        let mut interpreter = Interpreter::new();
+        let results = doc.forms().iter().map(|form| {
+            let result = interpreter.eval_top(form);
+            if let Err(e) = &result {
+                interpreter.print(format!("error: {e}"));
+            }
+            (form as *const _, result)
+        }).collect();
+        Self { interpreter, results, revision: doc.revision() }
+    }
+
+    /// Result of the top-level form `form`, `None` if it is not one of the
+    /// evaluated document.
+    pub fn result(&self, form: &Expr) -> Option<&Result<Value, String>>{
+        self.results.get(&(form as *const _))
+    }
+}
+
+/// Macros implemented natively.
+const MACROS: &[(&str, Expander)] = &[
+    ("loop", expand_loop),
+];
+
+/// The `loop` clauses `for x from a to/below b [by step]`, `repeat n`,
+/// `while cond` and `until cond`, followed by `do body...`, as a `let` of the
+/// counters around a `while`.
+///
+/// The bounds, steps and counts are evaluated once, into variables named
+/// after `%`.
+fn expand_loop(form: &Expr) -> Result<Expansion, String>{
+    let atom = |a: &str| Expr::Atom(a.to_owned());
+    let list = |items: &[Expr]| Expr::List(items.to_vec());
+    let forms = form.forms();
+    let mut bindings = Vec::new();
+    let mut tests = vec![atom("and")];
+    let mut steps = Vec::new();
+    // the paths in `bindings`, `tests` and `steps` of the copies of forms of
+    // the call, with their index in it
+    let mut binding_origins = Vec::new();
+    let mut test_origins = Vec::new();
+    let mut step_origins = Vec::new();
+    let mut at = 1;
+    let (body, body_at) = loop {
+        let Some((keyword, tail)) = forms.get(at..).unwrap_or_default().split_first() else {
+            return Err("`loop` without `do`".to_owned());
+        };
+        match (symbol(keyword)?, tail) {
+            ("do", body) => break (body, at + 1),
+            ("for", [var, from, start, dir, end, tail @ ..]) if symbol(from) == Ok("from") => {
+                let compare = match symbol(dir)? {
+                    "to" => "<=",
+                    "below" => "<",
+                    dir => return Err(format!("expected `to` or `below` in `loop`, found `{dir}`")),
+                };
+                let name = symbol(var)?;
+                let end_name = atom(&format!("%{name}-end"));
+                let var_at = at + 1;
+                binding_origins.extend([(vec![bindings.len(), 0], var_at), (vec![bindings.len(), 1], at + 3)]);
+                bindings.push(list(&[var.clone(), start.clone()]));
+                binding_origins.push((vec![bindings.len(), 1], at + 5));
+                bindings.push(list(&[end_name.clone(), end.clone()]));
+                test_origins.push((vec![tests.len(), 1], var_at));
+                tests.push(list(&[atom(compare), var.clone(), end_name]));
+                let step = match tail {
+                    [by, step, ..] if symbol(by) == Ok("by") => {
+                        let step_name = atom(&format!("%{name}-step"));
+                        binding_origins.push((vec![bindings.len(), 1], at + 7));
+                        bindings.push(list(&[step_name.clone(), step.clone()]));
+                        at += 8;
+                        step_name
+                    },
+                    _ => {
+                        at += 6;
+                        atom("1")
+                    },
+                };
+                step_origins.extend([(vec![steps.len(), 1], var_at), (vec![steps.len(), 2, 1], var_at)]);
+                steps.push(list(&[atom("setq"), var.clone(), list(&[atom("+"), var.clone(), step])]));
+            },
+            ("repeat", [n, ..]) => {
+                let count = atom("%repeat");
+                binding_origins.push((vec![bindings.len(), 1], at + 1));
+                bindings.push(list(&[count.clone(), n.clone()]));
+                tests.push(list(&[atom(">"), count.clone(), atom("0")]));
+                steps.push(list(&[atom("setq"), count.clone(), list(&[atom("-"), count, atom("1")])]));
+                at += 2;
+            },
+            ("while", [cond, ..]) => {
+                test_origins.push((vec![tests.len()], at + 1));
+                tests.push(cond.clone());
+                at += 2;
+            },
+            ("until", [cond, ..]) => {
+                test_origins.push((vec![tests.len(), 1], at + 1));
+                tests.push(list(&[atom("not"), cond.clone()]));
+                at += 2;
+            },
+            (clause, _) => return Err(format!("unsupported `loop` clause `{clause}`")),
+        }
+    };
+    let within = |prefix: &[usize], origins: Vec<(Vec<usize>, usize)>| {
+        origins.into_iter().map(move |(path, i)| ([prefix, path.as_slice()].concat(), vec![i])).collect::<Vec<_>>()
+    };
+    let mut origins = within(&[1], binding_origins);
+    origins.extend(within(&[2, 1], test_origins));
+    origins.extend((0..body.len()).map(|i| (vec![2, 2 + i], vec![body_at + i])));
+    let step_origins = step_origins.into_iter().map(|(mut path, i)| {
+        path[0] += 2 + body.len();
+        (path, i)
+    });
+    origins.extend(within(&[2], step_origins.collect()));
+    let mut looped = vec![atom("while"), Expr::List(tests)];
+    looped.extend(body.iter().cloned());
+    looped.extend(steps);
+    Ok(Expansion { form: list(&[atom("let"), Expr::List(bindings), Expr::List(looped)]), origins })
+}
+
+/// Records the path of `value`, found at `path` of a macro call, and of the
+/// values in it by their identity.
+fn index_values(value: &Value, path: &mut Vec<usize>, out: &mut HashMap<*const (), Vec<usize>>){
+    if let Some(identity) = value.identity() {
+        out.entry(identity).or_insert_with(|| path.clone());
+    }
+    if let Value::List(items) = value {
+        for (i, item) in items.iter().enumerate() {
+            path.push(i);
+            index_values(item, path, out);
+            path.pop();
+        }
+    }
+}
+
+/// The origins of the expansion `value`, at `path`, from the values of the
+/// call indexed by `index_values`.
+fn value_origins(value: &Value, arguments: &HashMap<*const (), Vec<usize>>, path: &mut Vec<usize>, out: &mut Origins){
+    if let Some(original) = value.identity().and_then(|identity| arguments.get(&identity)) {
+        out.push((path.clone(), original.clone()));
+    } else if let Value::List(items) = value {
+        for (i, item) in items.iter().enumerate() {
+            path.push(i);
+            value_origins(item, arguments, path, out);
+            path.pop();
+        }
+    }
+}
+
+/// The origins of `step`, an expansion of a form whose own origins are
+/// `origins`, followed back to where that form came from.
+fn follow(origins: &Origins, step: &Origins) -> Origins{
+    let mut out = Vec::new();
+    for (copy, original) in step {
+        for (form_copy, form_original) in origins {
+            if let Some(below) = original.strip_prefix(form_copy.as_slice()) {
+                out.push((copy.clone(), [form_original.as_slice(), below].concat()));
+            } else if let Some(below) = form_copy.strip_prefix(original.as_slice()) {
+                out.push(([copy.as_slice(), below].concat(), form_original.clone()));
+            }
+        }
+    }
+    out
+}
+
+fn symbol(expr: &Expr) -> Result<&str, String>{
+    match expr {
+        Expr::Atom(atom) if Value::literal(atom).is_none() => Ok(atom),
+        expr => Err(format!("expected a name, found {}", String::from(expr))),
+    }
+}
+
+fn lambda(name: Option<&str>, params: &Expr, body: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+    match params {
+        Expr::List(params) => lambda_from(name, params, body, env),
+        Expr::Atom(a) if a == "nil" => lambda_from(name, &[], body, env),
+        params => Err(format!("expected a parameter list, found {}", String::from(params))),
+    }
+}
+
+fn lambda_from(name: Option<&str>, params: &[Expr], body: &[Expr], env: &Rc<Env>) -> Result<Value, String>{
+    let mut names = params.iter().map(symbol);
+    let mut fixed = Vec::new();
+    let mut rest = None;
+    while let Some(param) = names.next() {
+        match param? {
+            "&rest" | "&body" | "." => {
+                let name = names.next().ok_or("expected a name after `&rest`")??;
+                rest = Some(name.to_owned());
+                if names.next().is_some() {
+                    return Err("only one name may follow `&rest`".to_owned());
+                }
+            },
+            param => fixed.push(param.to_owned()),
+        }
+    }
+    Ok(Value::Lambda(Rc::new(Lambda {
+        name: name.map(str::to_owned),
+        params: fixed,
+        rest,
+        body: body.into(),
+        env: env.clone(),
+    })))
+}
+
+fn number(v: &Value) -> Result<f64, String>{
+    match v {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        v => Err(format!("expected a number, found {v}")),
+    }
+}
+
+/// Folds numbers, staying in integers as long as every operand and result is
+/// one.
+fn arithmetic(
+    name: &str,
+    args: &[Value],
+    int: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value, String>{
+    let Some((first, rest)) = args.split_first() else {
+        return Err(format!("`{name}` takes at least one argument"));
+    };
+    rest.iter().try_fold(first.clone(), |acc, v| Ok(match (&acc, v) {
+        (Value::Int(a), Value::Int(b)) => match int(*a, *b) {
+            Some(x) => Value::Int(x),
+            None => Value::Float(float(*a as f64, *b as f64)),
+        },
+        _ => Value::Float(float(number(&acc)?, number(v)?)),
+    }))
+}
+
+/// `a / b` if that is a whole number in range, `i64::MIN / -1` is not.
+fn divide(a: i64, b: i64) -> Option<i64>{
+    (a.checked_rem(b)? == 0).then(|| a.checked_div(b)).flatten()
+}
+
+fn compare(args: &[Value], ok: fn(f64, f64) -> bool) -> Result<Value, String>{
+    let numbers = args.iter().map(number).collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::bool(numbers.windows(2).all(|w| ok(w[0], w[1]))))
+}
+
+fn equal(a: &Value, b: &Value) -> bool{
+    match (a, b) {
+        (Value::Nil, Value::Nil) | (Value::True, Value::True) => true,
+        (Value::Str(a), Value::Str(b)) | (Value::Symbol(a), Value::Symbol(b)) => a == b,
+        (Value::List(a), Value::List(b)) => a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b)),
+        (Value::Lambda(a), Value::Lambda(b)) => Rc::ptr_eq(a, b),
+        (a, b) => matches!((number(a), number(b)), (Ok(a), Ok(b)) if a == b),
+    }
+}
+
+const BUILTINS: &[(&str, Builtin)] = &[
+    ("+", |_, args| if args.is_empty() { Ok(Value::Int(0)) } else { arithmetic("+", args, i64::checked_add, |a, b| a + b) }),
+    ("*", |_, args| if args.is_empty() { Ok(Value::Int(1)) } else { arithmetic("*", args, i64::checked_mul, |a, b| a * b) }),
+    ("-", |_, args| match args {
+        [x] => arithmetic("-", &[Value::Int(0), x.clone()], i64::checked_sub, |a, b| a - b),
+        args => arithmetic("-", args, i64::checked_sub, |a, b| a - b),
+    }),
+    ("/", |_, args| {
+        let divisors = if args.len() == 1 { args } else { args.get(1..).unwrap_or_default() };
+        if divisors.iter().any(|d| matches!(d, Value::Int(0))) {
+            return Err("`/` by zero".to_owned());
+        }
+        match args {
+            [x] => arithmetic("/", &[Value::Int(1), x.clone()], divide, |a, b| a / b),
+            args => arithmetic("/", args, divide, |a, b| a / b),
+        }
+    }),
+    ("mod", |_, args| match args {
+        [_, Value::Int(0)] => Err("`mod` by zero".to_owned()),
+        [a, b] => arithmetic("mod", &[a.clone(), b.clone()], i64::checked_rem_euclid, f64::rem_euclid),
+        _ => Err("`mod` takes two arguments".to_owned()),
+    }),
+    ("=", |_, args| compare(args, |a, b| a == b)),
+    ("<", |_, args| compare(args, |a, b| a < b)),
+    (">", |_, args| compare(args, |a, b| a > b)),
+    ("<=", |_, args| compare(args, |a, b| a <= b)),
+    (">=", |_, args| compare(args, |a, b| a >= b)),
+    ("equal", |_, args| match args {
+        [a, b] => Ok(Value::bool(equal(a, b))),
+        _ => Err("`equal` takes two arguments".to_owned()),
+    }),
+    ("not", |_, args| match args {
+        [x] => Ok(Value::bool(!x.is_true())),
+        _ => Err("`not` takes one argument".to_owned()),
+    }),
+    ("list", |_, args| Ok(Value::list(args.to_vec()))),
+    ("cons", |_, args| match args {
+        [x, Value::Nil] => Ok(Value::list(vec![x.clone()])),
+        [x, Value::List(tail)] => Ok(Value::list(std::iter::once(x.clone()).chain(tail.iter().cloned()).collect())),
+        _ => Err("`cons` takes a value and a list".to_owned()),
+    }),
+    ("car", |_, args| match args {
+        [Value::Nil] => Ok(Value::Nil),
+        [Value::List(items)] => Ok(items[0].clone()),
+        _ => Err("`car` takes a list".to_owned()),
+    }),
+    ("cdr", |_, args| match args {
+        [Value::Nil] => Ok(Value::Nil),
+        [Value::List(items)] => Ok(Value::list(items[1..].to_vec())),
+        _ => Err("`cdr` takes a list".to_owned()),
+    }),
+    ("null", |_, args| match args {
+        [x] => Ok(Value::bool(!x.is_true())),
+        _ => Err("`null` takes one argument".to_owned()),
+    }),
+    ("print", |interp, args| {
+        let line = args.iter().map(Value::display).collect::<Vec<_>>().join(" ");
+        interp.print(line);
+        Ok(args.last().cloned().unwrap_or(Value::Nil))
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of the last form of `text`, or the first error.
+    fn eval(text: &str) -> Result<String, String>{
+        let mut interpreter = Interpreter::new();
+        let mut value = Value::Nil;
+        for form in reader::read_all(text)? {
+            value = interpreter.eval_top(&form)?;
+        }
+        Ok(value.to_string())
+    }
+
+    fn output(text: &str) -> Vec<String>{
+        let mut interpreter = Interpreter::new();
+        for form in reader::read_all(text).unwrap() {
+            interpreter.eval_top(&form).unwrap();
+        }
+        interpreter.output
+    }
+
+    #[test]
+    fn division_stays_whole_when_it_can(){
+        assert_eq!(eval("(/ 6 3)").unwrap(), "2");
+        assert_eq!(eval("(/ 7 2)").unwrap(), "3.5");
+        assert_eq!(eval("(/ 2)").unwrap(), "0.5");
+        assert_eq!(eval("(/ 1 0)").unwrap_err(), "`/` by zero");
+        assert_eq!(eval("(/ 0)").unwrap_err(), "`/` by zero");
+        assert_eq!(eval("(/ 0 2)").unwrap(), "0");
+    }
+
+    #[test]
+    fn overflow_falls_back_to_floats(){
+        assert_eq!(eval("(/ -9223372036854775808 -1)").unwrap(), "9.223372036854776e18");
+        assert_eq!(eval("(= 0 (mod -9223372036854775808 -1))").unwrap(), "t");
+        assert_eq!(eval("(+ 9223372036854775807 1)").unwrap(), "9.223372036854776e18");
+        assert_eq!(eval("(- -9223372036854775808)").unwrap(), "9.223372036854776e18");
+    }
+
+    #[test]
+    fn mod_is_never_negative(){
+        assert_eq!(eval("(mod -7 3)").unwrap(), "2");
+        assert_eq!(eval("(mod 7 -3)").unwrap(), "1");
+        assert_eq!(eval("(mod 7 0)").unwrap_err(), "`mod` by zero");
+    }
+
+    #[test]
+    fn let_evaluates_its_values_outside(){
+        assert_eq!(eval("(define x 1) (let ((x 2) (y x)) (list x y))").unwrap(), "(2 1)");
+    }
+
+    #[test]
+    fn and_or_stop_early(){
+        assert_eq!(eval("(and 1 nil (car 1))").unwrap(), "nil");
+        assert_eq!(eval("(or nil 2 (car 1))").unwrap(), "2");
+        assert_eq!(eval("(and)").unwrap(), "t");
+    }
+
+    #[test]
+    fn quasiquote_splices(){
+        assert_eq!(eval("(define xs '(2 3)) `(1 ,@xs ,(car xs))").unwrap(), "(1 2 3 2)");
+    }
+
+    #[test]
+    fn setq_needs_a_binding(){
+        assert_eq!(eval("(setq nope 1)").unwrap_err(), "`nope` is not defined");
+    }
+
+    #[test]
+    fn loop_counts_and_stops(){
+        assert_eq!(output("(loop for i from 0 below 10 by 3 do (print i))"), ["0", "3", "6", "9"]);
+        assert_eq!(output("(loop repeat 2 do (print 'r))"), ["r", "r"]);
+        assert_eq!(eval("(define n 0) (loop for i from 1 to 100 until (> n 5) do (setq n (+ n i))) n").unwrap(), "6");
+        assert_eq!(eval("(loop for i from 1 upto 3 do i)").unwrap_err(), "expected `to` or `below` in `loop`, found `upto`");
+        assert_eq!(eval("(loop for i from 1 to 3)").unwrap_err(), "`loop` without `do`");
+    }
+
+    #[test]
+    fn endless_loops_run_out_of_fuel(){
+        assert_eq!(eval("(while 1)").unwrap_err(), "evaluation took too many steps");
+        assert_eq!(eval("(defun f (x) (f x)) (f 1)").unwrap_err(), format!("evaluation nested deeper than {MAX_DEPTH}"));
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error(){
+        let mut expr = Expr::Atom("1".to_owned());
+        for _ in 0..3000 {
+            expr = Expr::List(vec![Expr::Atom("+".to_owned()), Expr::Atom("1".to_owned()), expr]);
+        }
+        let result = Interpreter::new().eval_top(&expr).map(|v| v.to_string());
+        assert_eq!(result, Err(format!("evaluation nested deeper than {MAX_DEPTH}")));
+        assert_eq!(eval("(defun f (n) (if (= n 0) 0 (+ 1 (f (- n 1))))) (f 100)").unwrap(), "100");
+    }
+
+    #[test]
+    fn macros_expand_before_evaluation(){
+        assert_eq!(eval("(defmacro twice (x) `(progn ,x ,x)) (define n 0) (twice (setq n (+ n 1))) n").unwrap(), "2");
+    }
+
+    /// The full expansion of the last form of `text` with the macros defined
+    /// before it, as text, and its origins.
+    fn expansion(text: &str) -> (String, Origins){
+        let forms = reader::read_all(text).unwrap();
+        let (call, definitions) = forms.split_last().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.define_macros(definitions).unwrap();
+        let expansion = interpreter.expand_all(call).unwrap();
+        (String::from(&expansion.form), expansion.origins)
+    }
+
+    #[test]
+    fn defmacro_origins_are_the_values_it_was_passed(){
+        // the quoted `x` is equal to the argument but not a copy of it
+        let (form, origins) = expansion("(defmacro m (x) `(list 'x ,x ,(car x))) (m (x 1))");
+        assert_eq!(form, "(list (quote x) (x 1) x)");
+        assert_eq!(origins, [(vec![2], vec![1]), (vec![3], vec![1, 0])]);
+    }
+
+    #[test]
+    fn loop_origins_point_into_the_clauses(){
+        let (form, origins) = expansion("(loop for i from 0 below n while (f i) do (g i))");
+        assert_eq!(form, "(let ((i 0) (%i-end n)) (while (and (< i %i-end) (f i)) (g i) (setq i (+ i 1))))");
+        assert_eq!(origins, [
+            (vec![1, 0, 0], vec![2]),
+            (vec![1, 0, 1], vec![4]),
+            (vec![1, 1, 1], vec![6]),
+            (vec![2, 1, 1, 1], vec![2]),
+            (vec![2, 1, 2], vec![8]),
+            (vec![2, 2], vec![10]),
+            (vec![2, 3, 1], vec![2]),
+            (vec![2, 3, 2, 1], vec![2]),
+        ]);
+    }
+
+    #[test]
+    fn expand_all_follows_origins_through_every_step(){
+        let (form, origins) = expansion("\
+            (define-syntax swap (syntax-rules () ((_ a b) (list b a))))
+            (define-syntax wrap (syntax-rules () ((_ x) (swap 0 (swap x 1)))))
+            (wrap (f 2))");
+        assert_eq!(form, "(list (list 1 (f 2)) 0)");
+        assert_eq!(origins, [(vec![1, 2], vec![1])]);
+    }
+}
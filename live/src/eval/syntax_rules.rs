@@ -0,0 +1,274 @@
+//! Pattern based macros, as in Scheme's `syntax-rules`.
+//!
+//! Patterns are matched against the unevaluated form and the template is
+//! filled in with what the pattern variables matched. An element followed by
+//! `...` matches any number of forms. Unlike Scheme, names introduced by a
+//! template are not renamed, so they can capture the caller's.
+
+use std::collections::HashMap;
+
+use super::{Expansion, Origins};
+use crate::Expr;
+
+const ELLIPSIS: &str = "...";
+
+pub struct SyntaxRules{
+    /// Names that match only themselves.
+    literals: Vec<String>,
+    /// Patterns and their templates, tried in order.
+    rules: Vec<(Expr, Expr)>,
+}
+
+#[derive(Clone)]
+enum Binding{
+    /// A form of the call and its path in it.
+    One(Expr, Vec<usize>),
+    /// A variable below an ellipsis, one binding per repetition.
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+fn is_ellipsis(expr: Option<&Expr>) -> bool{
+    matches!(expr, Some(Expr::Atom(a)) if a == ELLIPSIS)
+}
+
+impl SyntaxRules {
+    /// Parses `(syntax-rules (literals...) (pattern template)...)`.
+    pub fn parse(spec: &Expr) -> Result<Self, String>{
+        let shape = || "expected `(syntax-rules (literals...) (pattern template)...)`".to_owned();
+        let Expr::List(items) = spec else {
+            return Err(shape());
+        };
+        let [Expr::Atom(head), Expr::List(literals), rules @ ..] = items.as_slice() else {
+            return Err(shape());
+        };
+        if head != "syntax-rules" {
+            return Err(shape());
+        }
+        let literals = literals.iter().map(|l| match l {
+            Expr::Atom(name) => Ok(name.clone()),
+            _ => Err(shape()),
+        }).collect::<Result<_, _>>()?;
+        let rules = rules.iter().map(|rule| match rule {
+            Expr::List(rule) if rule.len() == 2 && matches!(rule[0], Expr::List(_)) => Ok((rule[0].clone(), rule[1].clone())),
+            rule => Err(format!("malformed rule {}", String::from(rule))),
+        }).collect::<Result<_, _>>()?;
+        Ok(Self { literals, rules })
+    }
+
+    /// Rewrites the macro call `form` with the first rule that matches it.
+    pub fn expand(&self, form: &Expr) -> Result<Expansion, String>{
+        let Expr::List(args) = form else {
+            return Err("macro call is not a list".to_owned());
+        };
+        for (pattern, template) in &self.rules {
+            let Expr::List(pattern) = pattern else { continue };
+            let mut bindings = Bindings::new();
+            // the keyword itself is not matched
+            if self.match_list(pattern.get(1..).unwrap_or_default(), args.get(1..).unwrap_or_default(), &mut Vec::new(), 1, &mut bindings) {
+                let mut origins = Vec::new();
+                let form = fill(template, &bindings, &mut Vec::new(), &mut origins)?;
+                return Ok(Expansion { form, origins });
+            }
+        }
+        Err(format!("no rule matches {}", String::from(form)))
+    }
+
+    /// Matches `form`, at `path` of the call, against `pattern`.
+    fn match_form(&self, pattern: &Expr, form: &Expr, path: &mut Vec<usize>, bindings: &mut Bindings) -> bool{
+        match pattern {
+            Expr::Atom(p) if p == "_" => true,
+            Expr::Atom(p) if self.literals.contains(p) => matches!(form, Expr::Atom(f) if f == p),
+            Expr::Atom(p) => {
+                bindings.insert(p.clone(), Binding::One(form.clone(), path.clone()));
+                true
+            },
+            Expr::List(pattern) => match form {
+                Expr::List(forms) => self.match_list(pattern, forms, path, 0, bindings),
+                Expr::Atom(_) => false,
+            },
+        }
+    }
+
+    /// `match_form` of the child `i` of the list at `path`.
+    fn match_child(&self, pattern: &Expr, form: &Expr, path: &mut Vec<usize>, i: usize, bindings: &mut Bindings) -> bool{
+        path.push(i);
+        let matched = self.match_form(pattern, form, path, bindings);
+        path.pop();
+        matched
+    }
+
+    /// Matches `forms`, the children of the list at `path` from index
+    /// `first` on, against `pattern`.
+    fn match_list(&self, pattern: &[Expr], forms: &[Expr], path: &mut Vec<usize>, first: usize, bindings: &mut Bindings) -> bool{
+        let Some(dots) = (0..pattern.len()).find(|&i| is_ellipsis(pattern.get(i + 1))) else {
+            return pattern.len() == forms.len()
+                && pattern.iter().zip(forms).enumerate().all(|(i, (p, f))| self.match_child(p, f, path, first + i, bindings));
+        };
+        let (before, repeated, after) = (&pattern[..dots], &pattern[dots], &pattern[dots + 2..]);
+        if forms.len() < before.len() + after.len() {
+            return false;
+        }
+        let (head, rest) = forms.split_at(before.len());
+        let (middle, tail) = rest.split_at(rest.len() - after.len());
+        let (middle_first, tail_first) = (first + before.len(), first + before.len() + middle.len());
+        if !self.match_list(before, head, path, first, bindings) || !self.match_list(after, tail, path, tail_first, bindings) {
+            return false;
+        }
+        let mut repetitions = Vec::new();
+        for (i, form) in middle.iter().enumerate() {
+            let mut inner = Bindings::new();
+            if !self.match_child(repeated, form, path, middle_first + i, &mut inner) {
+                return false;
+            }
+            repetitions.push(inner);
+        }
+        for name in variables(repeated, &self.literals) {
+            let each = repetitions.iter_mut().map(|r| r.remove(&name).unwrap()).collect();
+            bindings.insert(name, Binding::Many(each));
+        }
+        true
+    }
+}
+
+/// The pattern variables in `pattern`.
+fn variables(pattern: &Expr, literals: &[String]) -> Vec<String>{
+    match pattern {
+        Expr::Atom(p) if p == "_" || p == ELLIPSIS || literals.contains(p) => vec![],
+        Expr::Atom(p) => vec![p.clone()],
+        Expr::List(items) => items.iter().flat_map(|p| variables(p, literals)).collect(),
+    }
+}
+
+/// `template` filled in, at `path` of the expansion, adding the forms of the
+/// call it copies to `origins`.
+fn fill(template: &Expr, bindings: &Bindings, path: &mut Vec<usize>, origins: &mut Origins) -> Result<Expr, String>{
+    let items = match template {
+        Expr::Atom(name) => return match bindings.get(name) {
+            Some(Binding::One(form, original)) => {
+                origins.push((path.clone(), original.clone()));
+                Ok(form.clone())
+            },
+            Some(Binding::Many(_)) => Err(format!("`{name}` needs a `...` after it")),
+            None => Ok(template.clone()),
+        },
+        Expr::List(items) => items,
+    };
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < items.len() {
+        let item = &items[i];
+        if !is_ellipsis(items.get(i + 1)) {
+            out.push(fill_child(item, bindings, path, out.len(), origins)?);
+            i += 1;
+            continue;
+        }
+        // one copy of `item` per repetition of the variables below it
+        let repeated: Vec<_> = variables(item, &[])
+            .into_iter()
+            .filter_map(|name| match bindings.get(&name) {
+                Some(Binding::Many(each)) => Some((name, each)),
+                _ => None,
+            })
+            .collect();
+        let Some(count) = repeated.first().map(|(_, each)| each.len()) else {
+            return Err(format!("nothing to repeat in {}", String::from(item)));
+        };
+        if repeated.iter().any(|(_, each)| each.len() != count) {
+            return Err(format!("variables in {} repeat a different number of times", String::from(item)));
+        }
+        for k in 0..count {
+            let mut inner: Bindings = HashMap::new();
+            for (name, each) in &repeated {
+                inner.insert(name.clone(), each[k].clone());
+            }
+            for (name, binding) in bindings {
+                if !inner.contains_key(name) {
+                    inner.insert(name.clone(), binding.clone());
+                }
+            }
+            out.push(fill_child(item, &inner, path, out.len(), origins)?);
+        }
+        i += 2;
+    }
+    Ok(Expr::List(out))
+}
+
+/// `fill` of the child `i` of the list at `path`.
+fn fill_child(template: &Expr, bindings: &Bindings, path: &mut Vec<usize>, i: usize, origins: &mut Origins) -> Result<Expr, String>{
+    path.push(i);
+    let filled = fill(template, bindings, path, origins);
+    path.pop();
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader;
+
+    fn read(text: &str) -> Expr{
+        reader::read_all(text).unwrap().remove(0)
+    }
+
+    /// `form` expanded by the rules in `spec`, as text.
+    fn expand(spec: &str, form: &str) -> Result<String, String>{
+        SyntaxRules::parse(&read(spec))?.expand(&read(form)).map(|e| String::from(&e.form))
+    }
+
+    #[test]
+    fn ellipsis_matches_any_number(){
+        let spec = "(syntax-rules () ((_ x ...) (list x ...)))";
+        assert_eq!(expand(spec, "(m)").unwrap(), "(list)");
+        assert_eq!(expand(spec, "(m 1 (2) 3)").unwrap(), "(list 1 (2) 3)");
+    }
+
+    #[test]
+    fn ellipsis_leaves_room_for_the_rest(){
+        let spec = "(syntax-rules () ((_ first middle ... last) (list last middle ... first)))";
+        assert_eq!(expand(spec, "(m 1 2 3 4)").unwrap(), "(list 4 2 3 1)");
+        assert_eq!(expand(spec, "(m 1 2)").unwrap(), "(list 2 1)");
+        assert_eq!(expand(spec, "(m 1)").unwrap_err(), "no rule matches (m 1)");
+    }
+
+    #[test]
+    fn ellipsis_repeats_nested_patterns(){
+        let spec = "(syntax-rules () ((_ ((name value) ...) body ...) ((lambda (name ...) body ...) value ...)))";
+        assert_eq!(
+            expand(spec, "(my-let ((a 1) (b 2)) (+ a b))").unwrap(),
+            "((lambda (a b) (+ a b)) 1 2)",
+        );
+        assert_eq!(expand(spec, "(my-let ((a 1) b) a)").unwrap_err(), "no rule matches (my-let ((a 1) b) a)");
+    }
+
+    #[test]
+    fn literals_match_only_themselves(){
+        let spec = "(syntax-rules (=>) ((_ a => b) (b a)) ((_ a b c) (list a b c)))";
+        assert_eq!(expand(spec, "(m 1 => f)").unwrap(), "(f 1)");
+        assert_eq!(expand(spec, "(m 1 2 3)").unwrap(), "(list 1 2 3)");
+    }
+
+    #[test]
+    fn origins_are_the_paths_of_the_matched_forms(){
+        let rules = SyntaxRules::parse(&read("(syntax-rules () ((_ a (b ...)) (list b ... a (a) x)))")).unwrap();
+        let expansion = rules.expand(&read("(m x (1 (2)))")).unwrap();
+        assert_eq!(String::from(&expansion.form), "(list 1 (2) x (x) x)");
+        // the last `x` is the template's own
+        assert_eq!(expansion.origins, [
+            (vec![1], vec![2, 0]),
+            (vec![2], vec![2, 1]),
+            (vec![3], vec![1]),
+            (vec![4, 0], vec![1]),
+        ]);
+    }
+
+    #[test]
+    fn repeated_variables_need_an_ellipsis(){
+        let spec = "(syntax-rules () ((_ x ...) (list x)))";
+        assert_eq!(expand(spec, "(m 1 2)").unwrap_err(), "`x` needs a `...` after it");
+        let spec = "(syntax-rules () ((_ (a ...) (b ...)) (list (a b) ...)))";
+        assert_eq!(expand(spec, "(m (1 2) (3 4))").unwrap(), "(list (1 3) (2 4))");
+        assert_eq!(expand(spec, "(m (1 2) (3))").unwrap_err(), "variables in (a b) repeat a different number of times");
+    }
+}
@@ -0,0 +1,169 @@
+//! Lists collapsed to their head and a count of the rest.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::Expr;
+use crate::document::Document;
+use crate::persist::{self, Persist};
+
+pub struct Folds{
+    /// Paths of the folded lists in the document.
+    paths: BTreeSet<Vec<usize>>,
+    /// `Document::revision` the paths are of.
+    revision: u64,
+}
+
+impl Folds {
+    pub fn new() -> Self{
+        Self { paths: BTreeSet::new(), revision: 0 }
+    }
+
+    /// Forgets the folds if `doc` changed since they were made.
+    pub fn sync(&mut self, doc: &Document){
+        if self.revision != doc.revision() {
+            if !self.paths.is_empty() {
+                self.paths.clear();
+                doc.relayout();
+            }
+            self.revision = doc.revision();
+        }
+    }
+
+    /// Moves the folds to the new paths of their nodes after `doc` changed,
+    /// dropping the ones `follow` finds no node for.
+    pub fn follow(&mut self, doc: &Document, mut follow: impl FnMut(&[usize]) -> Option<Vec<usize>>){
+        self.paths = self.paths.iter().filter_map(|path| follow(path)).collect();
+        self.revision = doc.revision();
+    }
+
+    /// Folds the list at `path` of `doc`, or unfolds it if it is folded.
+    pub fn toggle(&mut self, doc: &Document, path: &[usize]){
+        if !self.paths.remove(path) {
+            self.paths.insert(path.to_vec());
+        }
+        doc.mark_dirty(path);
+    }
+
+    /// Folds the lists `depth` levels deep, the top-level forms being the
+    /// first level, and unfolds the ones above them. `None` unfolds
+    /// everything.
+    pub fn unfold_to_depth(&mut self, doc: &Document, depth: Option<usize>){
+        self.paths.clear();
+        if let Some(below) = depth.and_then(|depth| depth.checked_sub(1)) {
+            for (i, form) in doc.forms().iter().enumerate() {
+                collect(form, &mut vec![i], below, &mut self.paths);
+            }
+        }
+        doc.relayout();
+    }
+
+    /// The folded nodes of `doc`, by address.
+    pub fn nodes(&self, doc: &Document) -> HashSet<*const Expr>{
+        self.paths.iter().filter_map(|path| doc.get(path)).map(|node| node as *const Expr).collect()
+    }
+}
+
+/// Only the paths are kept, a loaded `Folds` is of no revision until
+/// `follow` moves it to the document's.
+impl Persist for Folds {
+    fn save(&self, w: &mut persist::Writer) {
+        self.paths.iter().cloned().collect::<Vec<_>>().save(w);
+    }
+    fn load(r: &mut persist::Reader) -> Option<Self> {
+        Some(Self { paths: Vec::load(r)?.into_iter().collect(), revision: u64::MAX })
+    }
+}
+
+/// The list at `path` of `doc`, or the one the atom at `path` is in, if it
+/// has more than its head to hide.
+pub fn foldable(doc: &Document, mut path: Vec<usize>) -> Option<Vec<usize>>{
+    if let Expr::Atom(_) = doc.get(&path)? {
+        path.pop();
+    }
+    match doc.get(&path)? {
+        Expr::List(children) if !path.is_empty() && children.len() > 1 => Some(path),
+        _ => None,
+    }
+}
+
+/// The paths of the lists at `depth` below `node`, which is at `path`.
+fn collect(node: &Expr, path: &mut Vec<usize>, depth: usize, out: &mut BTreeSet<Vec<usize>>){
+    let Expr::List(children) = node else {
+        return;
+    };
+    if depth == 0 {
+        if children.len() > 1 {
+            out.insert(path.clone());
+        }
+        return;
+    }
+    for (i, child) in children.iter().enumerate() {
+        path.push(i);
+        collect(child, path, depth - 1, out);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader;
+
+    fn doc(text: &str) -> Document{
+        Document::new(Expr::List(reader::read_all(text).unwrap()))
+    }
+
+    fn paths(folds: &Folds) -> Vec<Vec<usize>>{
+        folds.paths.iter().cloned().collect()
+    }
+
+    const TEXT: &str = "(a (b (c d)) (e)) x (f g)";
+
+    #[test]
+    fn depth_one_folds_the_top_level_forms(){
+        let doc = doc(TEXT);
+        let mut folds = Folds::new();
+        folds.unfold_to_depth(&doc, Some(1));
+        assert_eq!(paths(&folds), [vec![0], vec![2]]);
+        folds.unfold_to_depth(&doc, Some(2));
+        // `(e)` has nothing past its head to hide
+        assert_eq!(paths(&folds), [vec![0, 1]]);
+        folds.unfold_to_depth(&doc, Some(3));
+        assert_eq!(paths(&folds), [vec![0, 1, 1]]);
+        folds.unfold_to_depth(&doc, Some(0));
+        assert!(paths(&folds).is_empty());
+        folds.unfold_to_depth(&doc, Some(1));
+        folds.unfold_to_depth(&doc, None);
+        assert!(paths(&folds).is_empty());
+    }
+
+    #[test]
+    fn collect_takes_the_lists_at_the_depth(){
+        let doc = doc(TEXT);
+        let mut out = BTreeSet::new();
+        collect(doc.root(), &mut Vec::new(), 2, &mut out);
+        assert_eq!(out.into_iter().collect::<Vec<_>>(), [vec![0, 1]]);
+        let mut out = BTreeSet::new();
+        collect(&doc.forms()[1], &mut vec![1], 0, &mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn atoms_fold_their_list(){
+        let doc = doc(TEXT);
+        assert_eq!(foldable(&doc, vec![0, 1, 1, 0]), Some(vec![0, 1, 1]));
+        assert_eq!(foldable(&doc, vec![0, 1]), Some(vec![0, 1]));
+        assert_eq!(foldable(&doc, vec![2, 1]), Some(vec![2]));
+        // top-level atoms are in no list but the root
+        assert_eq!(foldable(&doc, vec![1]), None);
+        assert_eq!(foldable(&doc, vec![5]), None);
+    }
+
+    #[test]
+    fn lists_of_one_child_are_not_foldable(){
+        let doc = doc(TEXT);
+        assert_eq!(foldable(&doc, vec![0, 2]), None);
+        assert_eq!(foldable(&doc, vec![0, 2, 0]), None);
+        assert_eq!(foldable(&doc, vec![]), None);
+    }
+}
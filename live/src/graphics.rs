@@ -2,20 +2,30 @@ use std::borrow::Borrow;
 use std::ops::Deref;
 
 
+mod assets;
+mod draw_list;
+mod ligature;
 mod rect;
+mod text;
+pub use assets::*;
+pub use draw_list::Layer;
 pub use rect::*;
+pub use text::*;
 
-use ffi::DrawRectangleRoundedLines;
+use draw_list::{DrawList, Primitive};
 use raylib_ffi as ffi;
 use raylib_ffi::enums::*;
 pub use raylib_ffi::enums::KeyboardKey as Key;
+pub use raylib_ffi::enums::MouseButton;
 
 pub use ffi::Color;
 pub use raylib_ffi::colors;
 
 pub struct Graphics(());
+/// Collects the drawing of a frame, see `DrawList`.
 pub struct DrawHandle<'a>{
     pub g: &'a mut Graphics,
+    list: DrawList,
 }
 impl<'a> Deref for DrawHandle<'a> {
     type Target = &'a mut Graphics;
@@ -30,37 +40,47 @@ impl<'a> DrawHandle<'a> {
             ffi::GetTime()
         }
     }
+    /// Primitives drawn from now on go on `layer`.
+    pub fn set_layer(&mut self, layer: Layer){
+        self.list.set_layer(layer);
+    }
+    /// Clips everything drawn until the matching `pop_clip` to `rect`, and to
+    /// the clip rects pushed before it.
+    pub fn push_clip(&mut self, rect: Rect){
+        self.list.push_clip(rect);
+    }
+    pub fn pop_clip(&mut self){
+        self.list.pop_clip();
+    }
+    /// The innermost clip rect, `None` if drawing is not clipped.
+    pub fn clip_rect(&self) -> Option<Rect>{
+        self.list.clip_rect()
+    }
     pub fn clear_background(&mut self, color: Color){
-        unsafe{
-            ffi::ClearBackground(color)
-        }
+        self.list.clear(color);
     }
     pub fn draw_fps(&mut self, pos_x: i32, pos_y: i32){
-        unsafe{
-            ffi::DrawFPS(pos_x, pos_y)
-        }
+        self.list.push(None, Primitive::Fps { x: pos_x, y: pos_y });
     }
     pub fn draw_circle(&mut self, pos_x: i32, pos_y: i32, radius: f32, color: Color){
-        unsafe{
-            ffi::DrawCircle(pos_x, pos_y, radius, color)
-        }
+        self.list.push(None, Primitive::Circle { x: pos_x, y: pos_y, radius, color });
     }
     pub fn draw_text(&mut self, text: &str, pos_x: i32, pos_y: i32, font_size: i32, color: Color){
-        unsafe{
-            ffi::DrawText(ffi::rl_str!(text), pos_x, pos_y, font_size, color)
-        }
+        let text = text.to_owned();
+        self.list.push(None, Primitive::Text { text, x: pos_x, y: pos_y, size: font_size, color });
     }
     pub fn draw_text_ex(&mut self, font: PrettyFont, shader: &Shader,text: &str, pos: Vec2, scale: f32, color: Color){
-        unsafe{
-            ffi::BeginShaderMode(shader.0);
-            ffi::DrawTextEx(font.font, ffi::rl_str!(text), pos.into(), scale, 0.0, color);
-            ffi::EndShaderMode();
-        }
+        let text = text.to_owned();
+        self.list.push(Some(shader.0), Primitive::TextEx { font: font.font, text, pos, size: scale, color });
+    }
+    pub fn draw_rect(&mut self, rect: Rect, color: Color){
+        self.list.push(None, Primitive::Rect { rect, color });
+    }
+    pub fn draw_rect_rounded(&mut self, rect: Rect, roundness: f32, segments: i32, color: Color){
+        self.list.push(None, Primitive::Rounded { rect, roundness, segments, color });
     }
     pub fn draw_rect_rounded_lines(&mut self, rect: Rect, roundness: f32, segments: i32, line_thickness: f32, color: Color){
-        unsafe{
-            DrawRectangleRoundedLines(rect.into(), roundness, segments, line_thickness, color)
-        }
+        self.list.push(None, Primitive::RoundedLines { rect, roundness, segments, thickness: line_thickness, color });
     }
 }
 
@@ -78,15 +98,25 @@ impl Graphics{
         }
         Graphics(())
     }
+    /// Runs `f` to collect the frame, then draws it.
+    ///
+    /// Nothing is drawn if `f` panics.
     pub fn draw_frame<F: FnMut(&mut DrawHandle)>(&mut self, mut f: F){
+        let mut handle = DrawHandle{
+            g: self,
+            list: DrawList::default(),
+        };
+        f(&mut handle);
+        handle.list.flush();
+    }
+    pub fn screen_size(&self)->Vec2{
         unsafe{
-            ffi::BeginDrawing();
+            vec2(ffi::GetScreenWidth() as f32, ffi::GetScreenHeight() as f32)
         }
-        f(&mut DrawHandle{
-            g: self
-        });
+    }
+    pub fn mouse_wheel_move(&self)->f32{
         unsafe{
-            ffi::EndDrawing();
+            ffi::GetMouseWheelMove()
         }
     }
     pub fn is_key_pressed(&self, key: raylib_ffi::enums::KeyboardKey)->bool{
@@ -94,6 +124,43 @@ impl Graphics{
             ffi::IsKeyPressed(key as i32)
         }
     }
+    /// `true` when the OS repeats `key` because it is held down.
+    pub fn is_key_pressed_repeat(&self, key: raylib_ffi::enums::KeyboardKey)->bool{
+        unsafe{
+            ffi::IsKeyPressedRepeat(key as i32)
+        }
+    }
+    /// The next character typed this frame, call until `None` to get them all.
+    pub fn char_pressed(&self)->Option<char>{
+        unsafe{
+            char::from_u32(ffi::GetCharPressed() as u32).filter(|&c| c != '\0')
+        }
+    }
+    pub fn mouse_position(&self)->Vec2{
+        unsafe{
+            ffi::GetMousePosition().into()
+        }
+    }
+    pub fn is_mouse_button_down(&self, button: MouseButton)->bool{
+        unsafe{
+            ffi::IsMouseButtonDown(button as i32)
+        }
+    }
+    pub fn is_mouse_button_pressed(&self, button: MouseButton)->bool{
+        unsafe{
+            ffi::IsMouseButtonPressed(button as i32)
+        }
+    }
+    /// Saves the last frame drawn to `path`, the extension picks the format.
+    pub fn screenshot(&self, path: &str)->Result<(), String>{
+        let saved = unsafe{
+            let image = ffi::LoadImageFromScreen();
+            let saved = ffi::ExportImage(image, ffi::rl_str!(path));
+            ffi::UnloadImage(image);
+            saved
+        };
+        if saved { Ok(()) } else { Err(format!("could not save a screenshot to {path}")) }
+    }
 }
 
 impl Drop for Graphics {
@@ -119,6 +186,9 @@ impl FileData {
             Some(Self { ptr, size })
         }
     }
+    pub fn bytes(&self) -> &[u8]{
+        unsafe{ std::slice::from_raw_parts(self.ptr, self.size as usize) }
+    }
 }
 impl Drop for FileData {
     fn drop(&mut self) {
@@ -132,24 +202,50 @@ pub struct PrettyFont{
     font: ffi::Font,
 }
 impl Graphics {
-    pub fn font_from_file(&mut self, file: &FileData) -> PrettyFont{
+    /// Builds an SDF font with glyphs for `codepoints`, the ones standing for
+    /// ligatures drawn from `face`, the parsed `file`.
+    pub fn font_from_file(&mut self, file: &FileData, face: &ab_glyph::FontVec, codepoints: &[char]) -> Option<PrettyFont>{
         let base_size = 50;
+        let (ligatures, codepoints): (Vec<char>, Vec<char>) = codepoints
+            .iter()
+            .partition(|&&c| ligature::ligature_glyph(c).is_some());
+        let mut codepoints: Vec<i32> = codepoints.iter().map(|&c| c as i32).collect();
+        let glyph_count = (codepoints.len() + ligatures.len()) as i32;
         let font = unsafe {
             let mut sdf_font = ffi::Font{
                 baseSize: base_size,
-                glyphCount: 95,
+                glyphCount: glyph_count,
                 glyphPadding: 0,
-                glyphs: ffi::LoadFontData(file.ptr, file.size as i32, base_size, 0 as *mut i32, 0, FontType::Sdf as i32),
+                glyphs: ffi::LoadFontData(file.ptr, file.size as i32, base_size, codepoints.as_mut_ptr(), codepoints.len() as i32, FontType::Sdf as i32),
                 texture: ffi::Texture { id: 0, width: 0, height: 0, mipmaps: 0, format: 0, },
                 recs: 0 as *mut ffi::Rectangle,
             };
-            let atlas = ffi::GenImageFontAtlas(sdf_font.glyphs, &mut sdf_font.recs, 95, base_size, 0, 1);
+            if sdf_font.glyphs.is_null() {
+                return None;
+            }
+            if !ligatures.is_empty() {
+                // freed by raylib with the rest of the glyphs
+                let size = glyph_count as usize * std::mem::size_of::<ffi::GlyphInfo>();
+                sdf_font.glyphs = ffi::MemRealloc(sdf_font.glyphs as *mut _, size as u32) as *mut ffi::GlyphInfo;
+                for (i, &c) in ligatures.iter().enumerate() {
+                    let id = ligature::ligature_glyph(c).unwrap();
+                    let glyph = ligature::sdf_glyph(face, id, base_size);
+                    sdf_font.glyphs.add(codepoints.len() + i).write(glyph);
+                }
+            }
+            let atlas = ffi::GenImageFontAtlas(sdf_font.glyphs, &mut sdf_font.recs, glyph_count, base_size, 0, 1);
             sdf_font.texture = ffi::LoadTextureFromImage(atlas);
             ffi::SetTextureFilter(sdf_font.texture, TextureFilter::Bilinear as i32);    // Required for SDF font
             ffi::UnloadImage(atlas);
             sdf_font
         };
-        PrettyFont { font }
+        Some(PrettyFont { font })
+    }
+
+    pub fn unload_font(&mut self, font: PrettyFont){
+        unsafe{
+            ffi::UnloadFont(font.font);
+        }
     }
 
     pub fn draw_text(&mut self, font: &PrettyFont, text: &str, pos: ffi::Vector2, size: f32, color: ffi::Color){
@@ -169,6 +265,14 @@ impl Graphics {
 }
 
 pub struct Shader(ffi::Shader);
+impl Shader {
+    /// Whether this is raylib's built in shader, which it hands out when
+    /// compiling a shader fails.
+    pub fn is_default(&self) -> bool{
+        let default = unsafe{ ffi::LoadShader(0 as *const i8, 0 as *const i8) };
+        self.0.id == default.id
+    }
+}
 impl Drop for Shader {
     fn drop(&mut self) {
         println!("DROP: Shader");
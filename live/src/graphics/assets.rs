@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ab_glyph::Font as _;
+
+use super::*;
+
+/// Environment variable naming an extra directory to look for assets in.
+const ASSETS_ENV_VAR: &str = "SEE_ASSETS";
+
+struct Watched<T>{
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    value: T,
+    /// Why the last attempt to reload `value` failed, until one succeeds.
+    error: Option<String>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime>{
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A font file and the atlas built from it.
+struct FontAtlas{
+    data: FileData,
+    /// Parsed copy of `data`, to find out which glyphs the file has.
+    face: ab_glyph::FontVec,
+    ligatures: ligature::Ligatures,
+    /// Codepoints in the atlas, including the ones standing for ligatures.
+    codepoints: BTreeSet<char>,
+    /// Set when `codepoints` gained entries the atlas lacks.
+    stale: bool,
+    font: PrettyFont,
+}
+impl Drop for FontAtlas {
+    fn drop(&mut self) {
+        println!("DROP: FontAtlas");
+        unsafe{
+            ffi::UnloadFont(self.font.font);
+        }
+    }
+}
+
+/// Printable ASCII, always in the atlas.
+fn base_codepoints() -> BTreeSet<char>{
+    (' '..='~').collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct FontId(usize);
+
+/// Fonts tried in order for each glyph, the first one also stands in for
+/// glyphs none of them have.
+pub struct FontChain{
+    pub(super) fonts: Vec<FontId>,
+    pub(super) cache: RefCell<ShapeCache>,
+}
+#[derive(Clone, Copy)]
+pub struct ShaderId(usize);
+
+/// Fonts and shaders loaded from files, reloaded when the files change.
+pub struct Assets{
+    roots: Vec<PathBuf>,
+    fonts: Vec<Watched<FontAtlas>>,
+    shaders: Vec<Watched<Shader>>,
+    /// Bumped whenever a font file is reloaded, which changes its metrics.
+    pub(super) generation: u64,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Assets {
+    /// Assets are looked up in `$SEE_ASSETS`, the working directory, and the
+    /// directory of the executable and its ancestors, so both `cargo run` and
+    /// running `target/debug/see` directly find the files in the repository.
+    pub fn new() -> Self{
+        let mut roots = Vec::new();
+        if let Some(dir) = std::env::var_os(ASSETS_ENV_VAR) {
+            roots.push(dir.into());
+        }
+        if let Ok(dir) = std::env::current_dir() {
+            roots.push(dir);
+        }
+        if let Ok(exe) = std::env::current_exe() {
+            roots.extend(exe.ancestors().skip(1).map(Path::to_owned));
+        }
+        Self { roots, fonts: Vec::new(), shaders: Vec::new(), generation: 0 }
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, String>{
+        self.roots
+            .iter()
+            .map(|root| root.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("could not find {name}, set ${ASSETS_ENV_VAR} to the directory containing it"))
+    }
+
+    pub fn load_font(&mut self, g: &mut Graphics, name: &str) -> Result<FontId, String>{
+        let path = self.resolve(name)?;
+        let modified = modified(&path);
+        let value = load_font(g, &path, base_codepoints())?;
+        self.fonts.push(Watched { path, modified, value, error: None });
+        Ok(FontId(self.fonts.len() - 1))
+    }
+
+    /// Loads the fonts that exist, failing only if none does.
+    pub fn load_font_chain(&mut self, g: &mut Graphics, names: &[String]) -> Result<FontChain, String>{
+        let mut fonts = Vec::new();
+        let mut errors = Vec::new();
+        for name in names {
+            match self.load_font(g, name) {
+                Ok(id) => fonts.push(id),
+                Err(e) => errors.push(e),
+            }
+        }
+        if fonts.is_empty() {
+            errors.insert(0, "could not load any font".to_owned());
+            return Err(errors.join("\n"));
+        }
+        for e in errors {
+            eprintln!("skipping fallback font: {e}");
+        }
+        Ok(FontChain { fonts, cache: Default::default() })
+    }
+
+    pub fn load_shader(&mut self, g: &mut Graphics, fragment_shader: &str) -> Result<ShaderId, String>{
+        let path = self.resolve(fragment_shader)?;
+        let modified = modified(&path);
+        let value = load_shader(g, &path)?;
+        self.shaders.push(Watched { path, modified, value, error: None });
+        Ok(ShaderId(self.shaders.len() - 1))
+    }
+
+    pub fn font(&self, id: FontId) -> PrettyFont{
+        self.fonts[id.0].value.font
+    }
+
+    /// The first font of the chain that has a glyph for `c`.
+    pub fn font_for(&self, chain: &FontChain, c: char) -> FontId{
+        chain.fonts
+            .iter()
+            .copied()
+            .find(|&id| self.face(id).glyph_id(c).0 != 0)
+            .unwrap_or(chain.fonts[0])
+    }
+
+    /// Makes sure the chain can draw every glyph `text` is shaped into.
+    ///
+    /// Missing glyphs are added to the atlas of the font that has them on the
+    /// next `poll`.
+    pub fn request_glyphs(&mut self, chain: &FontChain, text: &str){
+        for (font, _, c) in self.glyphs(chain, text) {
+            let atlas = &mut self.fonts[font.0].value;
+            if atlas.codepoints.insert(c) {
+                atlas.stale = true;
+            }
+        }
+    }
+
+    pub(super) fn face(&self, id: FontId) -> &ab_glyph::FontVec{
+        &self.fonts[id.0].value.face
+    }
+
+    pub(super) fn ligatures(&self, id: FontId) -> &ligature::Ligatures{
+        &self.fonts[id.0].value.ligatures
+    }
+
+    /// Changes whenever font metrics may have.
+    pub fn generation(&self) -> u64{
+        self.generation
+    }
+
+    pub fn shader(&self, id: ShaderId) -> &Shader{
+        &self.shaders[id.0].value
+    }
+
+    /// Reloads every asset whose file changed and grows font atlases that
+    /// are missing requested glyphs.
+    ///
+    /// An asset that fails to load keeps its old value, the errors are
+    /// returned, and kept in `error` until the asset loads again.
+    pub fn poll(&mut self, g: &mut Graphics) -> Vec<String>{
+        let mut errors = Vec::new();
+        for font in &mut self.fonts {
+            let now = modified(&font.path);
+            if now != font.modified {
+                font.modified = now;
+                match load_font(g, &font.path, font.value.codepoints.clone()) {
+                    Ok(new) => {
+                        font.value = new;
+                        font.error = None;
+                        self.generation += 1;
+                        println!("reloaded {}", font.path.display());
+                    },
+                    Err(e) => {
+                        errors.push(e.clone());
+                        font.error = Some(e);
+                    },
+                }
+            }
+            let atlas = &mut font.value;
+            if atlas.stale {
+                atlas.stale = false;
+                let codepoints: Vec<char> = atlas.codepoints.iter().copied().collect();
+                match g.font_from_file(&atlas.data, &atlas.face, &codepoints) {
+                    Some(new) => {
+                        let old = std::mem::replace(&mut atlas.font, new);
+                        g.unload_font(old);
+                    },
+                    None => {
+                        let e = format!("could not add glyphs to {}", font.path.display());
+                        errors.push(e.clone());
+                        font.error = Some(e);
+                    },
+                }
+            }
+        }
+        for shader in &mut self.shaders {
+            let now = modified(&shader.path);
+            if now == shader.modified {
+                continue;
+            }
+            shader.modified = now;
+            match load_shader(g, &shader.path) {
+                Ok(new) => {
+                    shader.value = new;
+                    shader.error = None;
+                    println!("reloaded {}", shader.path.display());
+                },
+                Err(e) => {
+                    errors.push(e.clone());
+                    shader.error = Some(e);
+                },
+            }
+        }
+        errors
+    }
+
+    /// The errors of the assets that failed to load the last time their
+    /// files changed, `None` once they all loaded again.
+    pub fn error(&self) -> Option<String>{
+        let fonts = self.fonts.iter().filter_map(|font| font.error.as_deref());
+        let shaders = self.shaders.iter().filter_map(|shader| shader.error.as_deref());
+        let errors: Vec<&str> = fonts.chain(shaders).collect();
+        (!errors.is_empty()).then(|| errors.join("\n"))
+    }
+}
+
+fn load_font(g: &mut Graphics, path: &Path, codepoints: BTreeSet<char>) -> Result<FontAtlas, String>{
+    let path_str = path.to_string_lossy();
+    let data = FileData::load(&path_str).ok_or_else(|| format!("could not read {path_str}"))?;
+    let chars: Vec<char> = codepoints.iter().copied().collect();
+    let face = ab_glyph::FontVec::try_from_vec(data.bytes().to_vec())
+        .map_err(|e| format!("{path_str} is not a usable font: {e}"))?;
+    let ligatures = ligature::Ligatures::parse(data.bytes());
+    let font = g.font_from_file(&data, &face, &chars).ok_or_else(|| format!("{path_str} is not a usable font"))?;
+    Ok(FontAtlas { data, face, ligatures, codepoints, stale: false, font })
+}
+
+fn load_shader(g: &mut Graphics, path: &Path) -> Result<Shader, String>{
+    let path_str = path.to_string_lossy();
+    let shader = g.load_shader(None, &path_str);
+    // raylib falls back to its default shader when compiling fails
+    if shader.is_default() {
+        return Err(format!("{path_str} failed to compile, see the log above"));
+    }
+    Ok(shader)
+}
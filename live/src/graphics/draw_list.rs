@@ -0,0 +1,183 @@
+//! Primitives collected during a frame and drawn in batches at its end.
+
+use super::*;
+
+/// Draw order across batches, primitives of a higher layer are drawn on top.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Layer{
+    #[default]
+    Content,
+    /// Panels docked over the content, like the output of a run.
+    Panel,
+    Overlay,
+}
+
+/// Draw order of the primitives within a layer: boxes are filled, then
+/// outlined, then the text goes on top.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Pass{
+    Fill,
+    Outline,
+    Text,
+}
+
+pub(super) enum Primitive{
+    Fps{ x: i32, y: i32 },
+    /// Text in raylib's default font.
+    Text{ text: String, x: i32, y: i32, size: i32, color: Color },
+    TextEx{ font: ffi::Font, text: String, pos: Vec2, size: f32, color: Color },
+    Glyphs{ font: ffi::Font, glyphs: Vec<(char, Vec2)>, size: f32, color: Color },
+    Circle{ x: i32, y: i32, radius: f32, color: Color },
+    Rect{ rect: Rect, color: Color },
+    Rounded{ rect: Rect, roundness: f32, segments: i32, color: Color },
+    RoundedLines{ rect: Rect, roundness: f32, segments: i32, thickness: f32, color: Color },
+}
+
+impl Primitive {
+    fn pass(&self) -> Pass{
+        match self {
+            Primitive::Circle { .. } | Primitive::Rect { .. } | Primitive::Rounded { .. } => Pass::Fill,
+            Primitive::RoundedLines { .. } => Pass::Outline,
+            Primitive::Fps { .. } | Primitive::Text { .. } | Primitive::TextEx { .. } | Primitive::Glyphs { .. } => Pass::Text,
+        }
+    }
+
+    /// The font atlas drawn from, `None` for shapes and raylib's default font.
+    fn texture(&self) -> Option<u32>{
+        match self {
+            Primitive::TextEx { font, .. } | Primitive::Glyphs { font, .. } => Some(font.texture.id),
+            _ => None,
+        }
+    }
+
+    unsafe fn draw(self){
+        match self {
+            Primitive::Fps { x, y } => ffi::DrawFPS(x, y),
+            Primitive::Text { text, x, y, size, color } =>
+                ffi::DrawText(ffi::rl_str!(text), x, y, size, color),
+            Primitive::TextEx { font, text, pos, size, color } =>
+                ffi::DrawTextEx(font, ffi::rl_str!(text), pos.into(), size, 0.0, color),
+            Primitive::Glyphs { font, glyphs, size, color } => {
+                for (c, pos) in glyphs {
+                    ffi::DrawTextCodepoint(font, c as i32, pos.into(), size, color);
+                }
+            },
+            Primitive::Circle { x, y, radius, color } => ffi::DrawCircle(x, y, radius, color),
+            Primitive::Rect { rect, color } => ffi::DrawRectangleRec(rect.into(), color),
+            Primitive::Rounded { rect, roundness, segments, color } =>
+                ffi::DrawRectangleRounded(rect.into(), roundness, segments, color),
+            Primitive::RoundedLines { rect, roundness, segments, thickness, color } =>
+                ffi::DrawRectangleRoundedLines(rect.into(), roundness, segments, thickness, color),
+        }
+    }
+}
+
+struct Cmd{
+    layer: Layer,
+    /// Index into `DrawList::clips`, `None` if unclipped.
+    clip: Option<usize>,
+    /// `None` for raylib's default shader.
+    shader: Option<ffi::Shader>,
+    primitive: Primitive,
+}
+
+#[derive(Default)]
+pub(super) struct DrawList{
+    clear: Option<Color>,
+    layer: Layer,
+    /// Every clip rect pushed this frame, already intersected with its parents.
+    clips: Vec<Rect>,
+    /// Indices into `clips` of the pushed clip rects, innermost last.
+    clip_stack: Vec<usize>,
+    cmds: Vec<Cmd>,
+}
+
+impl DrawList {
+    pub(super) fn clear(&mut self, color: Color){
+        self.clear = Some(color);
+    }
+
+    pub(super) fn set_layer(&mut self, layer: Layer){
+        self.layer = layer;
+    }
+
+    pub(super) fn clip_rect(&self) -> Option<Rect>{
+        self.clip_stack.last().map(|&i| self.clips[i])
+    }
+
+    pub(super) fn push_clip(&mut self, rect: Rect){
+        let rect = match self.clip_rect() {
+            Some(parent) => parent.intersect(rect),
+            None => rect,
+        };
+        self.clip_stack.push(self.clips.len());
+        self.clips.push(rect);
+    }
+
+    pub(super) fn pop_clip(&mut self){
+        self.clip_stack.pop().expect("`pop_clip` without a matching `push_clip`");
+    }
+
+    pub(super) fn push(&mut self, shader: Option<ffi::Shader>, primitive: Primitive){
+        let clip = self.clip_stack.last().copied();
+        if clip.is_some_and(|i| !self.clips[i].is_positive()) {
+            return;
+        }
+        self.cmds.push(Cmd { layer: self.layer, clip, shader, primitive });
+    }
+
+    /// Draws the frame.
+    ///
+    /// Primitives are drawn layer by layer, and within a layer in passes:
+    /// fills, then outlines, then text. A pass is sorted by clip rect, shader
+    /// and texture, so that all the text of a layer is usually drawn with a
+    /// single switch to the SDF shader. Primitives that share all of these
+    /// keep the order they were pushed in.
+    pub(super) fn flush(mut self){
+        debug_assert!(self.clip_stack.is_empty(), "`push_clip` without a matching `pop_clip`");
+        // stable, keeps the order of primitives with the same key
+        self.cmds.sort_by_key(|cmd| {
+            (cmd.layer, cmd.primitive.pass(), cmd.clip, cmd.shader.map(|s| s.id), cmd.primitive.texture())
+        });
+        unsafe{
+            ffi::BeginDrawing();
+            if let Some(color) = self.clear {
+                ffi::ClearBackground(color);
+            }
+            let mut current: Option<u32> = None;
+            let mut clip: Option<usize> = None;
+            for cmd in self.cmds {
+                if cmd.clip != clip {
+                    if clip.is_some() {
+                        ffi::EndScissorMode();
+                    }
+                    if let Some(i) = cmd.clip {
+                        let rect = self.clips[i];
+                        let min = (rect.min.x.floor() as i32, rect.min.y.floor() as i32);
+                        let max = (rect.max.x.ceil() as i32, rect.max.y.ceil() as i32);
+                        ffi::BeginScissorMode(min.0, min.1, max.0 - min.0, max.1 - min.1);
+                    }
+                    clip = cmd.clip;
+                }
+                let shader = cmd.shader.map(|s| s.id);
+                if shader != current {
+                    if current.is_some() {
+                        ffi::EndShaderMode();
+                    }
+                    if let Some(shader) = cmd.shader {
+                        ffi::BeginShaderMode(shader);
+                    }
+                    current = shader;
+                }
+                cmd.primitive.draw();
+            }
+            if current.is_some() {
+                ffi::EndShaderMode();
+            }
+            if clip.is_some() {
+                ffi::EndScissorMode();
+            }
+            ffi::EndDrawing();
+        }
+    }
+}
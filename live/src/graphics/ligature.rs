@@ -0,0 +1,240 @@
+//! Ligatures from the GSUB tables of the fonts, and the atlas glyphs drawn
+//! for them.
+//!
+//! raylib's atlas holds glyphs by codepoint and a ligature glyph has none, so
+//! each one is kept in the atlas under a codepoint of the last private use
+//! plane made from its glyph id. Text using that plane itself is not drawn.
+
+use std::collections::HashMap;
+
+use ab_glyph::{Font as _, GlyphId, PxScale, ScaleFont as _};
+use ttf_parser::gsub::SubstitutionSubtable;
+use ttf_parser::opentype_layout::Coverage;
+use ttf_parser::Tag;
+
+use super::*;
+
+/// Start of plane 16, private use.
+const LIGATURE_BASE: u32 = 0x10_0000;
+
+/// The GSUB features forming ligatures that are on by default.
+const FEATURES: [&[u8; 4]; 2] = [b"liga", b"clig"];
+
+/// Padding around raylib's SDF glyphs, in pixels of the atlas.
+const SDF_PADDING: i32 = 4;
+/// Value of raylib's SDF glyphs on the outline, the shader's threshold.
+const SDF_ON_EDGE: f32 = 128.0;
+/// How much raylib's SDF values change per pixel away from the outline.
+const SDF_PIXEL_DIST_SCALE: f32 = 64.0;
+/// Pixels further from the outline than this saturate.
+const SDF_REACH: i32 = 3;
+
+/// The codepoint the ligature glyph `id` has in the atlas.
+pub(super) fn ligature_char(id: GlyphId) -> char{
+    char::from_u32(LIGATURE_BASE + id.0 as u32).expect("plane 16 has a codepoint for every glyph id")
+}
+
+/// The ligature glyph `c` stands for in the atlas, if it is one.
+pub(super) fn ligature_glyph(c: char) -> Option<GlyphId>{
+    (c as u32).checked_sub(LIGATURE_BASE).map(|id| GlyphId(id as u16))
+}
+
+/// The ligatures of a lookup by their first glyph: the glyphs following it
+/// and the ligature, preferred ones first.
+type Lookup = HashMap<GlyphId, Vec<(Vec<GlyphId>, GlyphId)>>;
+
+/// The ligature substitutions of a font.
+#[derive(Default)]
+pub(super) struct Ligatures{
+    /// In the order they apply.
+    lookups: Vec<Lookup>,
+}
+
+impl Ligatures {
+    /// The ligatures of the font in `data` that are formed by default, none
+    /// if it has no GSUB table.
+    ///
+    /// Lookup flags are not honored, no glyphs are skipped while matching.
+    pub(super) fn parse(data: &[u8]) -> Self{
+        let Some(gsub) = ttf_parser::Face::parse(data, 0).ok().and_then(|face| face.tables().gsub) else {
+            return Self::default();
+        };
+        let mut indices: Vec<u16> = (0..gsub.features.len())
+            .filter_map(|i| gsub.features.get(i))
+            .filter(|feature| FEATURES.iter().any(|tag| feature.tag == Tag::from_bytes(tag)))
+            .flat_map(|feature| feature.lookup_indices)
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        let mut lookups = Vec::new();
+        for lookup in indices.into_iter().filter_map(|i| gsub.lookups.get(i)) {
+            let mut by_first = Lookup::new();
+            for subtable in lookup.subtables.into_iter::<SubstitutionSubtable>() {
+                let SubstitutionSubtable::Ligature(subst) = subtable else {
+                    continue;
+                };
+                for (first, index) in covered(subst.coverage) {
+                    let Some(set) = subst.ligature_sets.get(index) else {
+                        continue;
+                    };
+                    let ligatures = by_first.entry(GlyphId(first)).or_default();
+                    for ligature in set {
+                        let rest = ligature.components.into_iter().map(|id| GlyphId(id.0)).collect();
+                        ligatures.push((rest, GlyphId(ligature.glyph.0)));
+                    }
+                }
+            }
+            if !by_first.is_empty() {
+                lookups.push(by_first);
+            }
+        }
+        Self { lookups }
+    }
+
+    /// Replaces the runs of glyphs in `glyphs` that form ligatures with
+    /// them, along with their chars.
+    pub(super) fn apply(&self, glyphs: &mut Vec<(GlyphId, char)>){
+        for lookup in &self.lookups {
+            let mut i = 0;
+            while i < glyphs.len() {
+                let following = &glyphs[i + 1..];
+                let found = lookup.get(&glyphs[i].0).and_then(|ligatures| {
+                    ligatures.iter().find(|(rest, _)| {
+                        following.iter().map(|&(id, _)| id).take(rest.len()).eq(rest.iter().copied())
+                    })
+                });
+                if let Some((rest, ligature)) = found {
+                    glyphs.splice(i..=i + rest.len(), [(*ligature, ligature_char(*ligature))]);
+                }
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The glyphs of `coverage` with their coverage index.
+fn covered(coverage: Coverage) -> Vec<(u16, u16)>{
+    match coverage {
+        Coverage::Format1 { glyphs } => glyphs.into_iter().zip(0..).map(|(id, i)| (id.0, i)).collect(),
+        Coverage::Format2 { records } => records
+            .into_iter()
+            .flat_map(|r| (r.start.0..=r.end.0).map(move |id| (id, r.value.wrapping_add(id - r.start.0))))
+            .collect(),
+    }
+}
+
+/// An atlas glyph for the ligature `id` of `face` at `size` pixels, laid out
+/// like the ones raylib makes for codepoints so the SDF shader draws it the
+/// same.
+///
+/// The distances are measured to the nearest pixel on the other side of the
+/// outline, and from the coverage for pixels the outline crosses.
+pub(super) fn sdf_glyph(face: &ab_glyph::FontVec, id: GlyphId, size: i32) -> ffi::GlyphInfo{
+    let scaled = face.as_scaled(PxScale::from(size as f32));
+    let value = ligature_char(id) as i32;
+    let advance = scaled.h_advance(id) as i32;
+    let Some(outline) = scaled.outline_glyph(id.with_scale(size as f32)) else {
+        return ffi::GlyphInfo { value, offsetX: 0, offsetY: 0, advanceX: advance, image: gray_image(1, 1, &[0]) };
+    };
+    let bounds = outline.px_bounds();
+    let width = bounds.width() as i32 + 2 * SDF_PADDING;
+    let height = bounds.height() as i32 + 2 * SDF_PADDING;
+    let mut coverage = vec![0.0; (width * height) as usize];
+    outline.draw(|x, y, c| {
+        coverage[((y as i32 + SDF_PADDING) * width + x as i32 + SDF_PADDING) as usize] = c;
+    });
+    let inside = |x: i32, y: i32| {
+        (0..width).contains(&x) && (0..height).contains(&y) && coverage[(y * width + x) as usize] >= 0.5
+    };
+    let mut sdf = Vec::with_capacity(coverage.len());
+    for y in 0..height {
+        for x in 0..width {
+            let c = coverage[(y * width + x) as usize];
+            let distance = if c > 0.0 && c < 1.0 {
+                c - 0.5
+            } else {
+                let side = inside(x, y);
+                let mut nearest = SDF_REACH as f32;
+                for dy in -SDF_REACH..=SDF_REACH {
+                    for dx in -SDF_REACH..=SDF_REACH {
+                        if inside(x + dx, y + dy) != side {
+                            nearest = nearest.min(((dx * dx + dy * dy) as f32).sqrt() - 0.5);
+                        }
+                    }
+                }
+                if side { nearest } else { -nearest }
+            };
+            sdf.push((SDF_ON_EDGE + SDF_PIXEL_DIST_SCALE * distance).clamp(0.0, 255.0) as u8);
+        }
+    }
+    ffi::GlyphInfo {
+        value,
+        offsetX: bounds.min.x as i32 - SDF_PADDING,
+        offsetY: bounds.min.y as i32 - SDF_PADDING + scaled.ascent() as i32,
+        advanceX: advance,
+        image: gray_image(width, height, &sdf),
+    }
+}
+
+/// A grayscale image owned by raylib, freed with the font it ends up in.
+fn gray_image(width: i32, height: i32, pixels: &[u8]) -> ffi::Image{
+    unsafe {
+        let data = ffi::MemAlloc(pixels.len() as u32) as *mut u8;
+        std::ptr::copy_nonoverlapping(pixels.as_ptr(), data, pixels.len());
+        ffi::Image {
+            data: data as *mut _,
+            width,
+            height,
+            mipmaps: 1,
+            format: PixelFormat::UncompressedGrayscale as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs(ids: &[u16]) -> Vec<(GlyphId, char)>{
+        ids.iter().map(|&id| (GlyphId(id), char::from(b'a' + id as u8))).collect()
+    }
+
+    #[test]
+    fn ligature_chars_stand_for_their_glyph(){
+        let c = ligature_char(GlyphId(0xffff));
+        assert_eq!(c, '\u{10ffff}');
+        assert_eq!(ligature_glyph(c), Some(GlyphId(0xffff)));
+        assert_eq!(ligature_glyph('f'), None);
+    }
+
+    #[test]
+    fn forms_the_first_ligature_that_matches(){
+        // f f i => ffi before f f => ff, and f i => fi
+        let (f, i, x) = (GlyphId(5), GlyphId(8), GlyphId(23));
+        let (ffi, ff, fi) = (GlyphId(100), GlyphId(101), GlyphId(102));
+        let ligatures = Ligatures {
+            lookups: vec![HashMap::from([(f, vec![(vec![f, i], ffi), (vec![f], ff), (vec![i], fi)])])],
+        };
+        let mut run = glyphs(&[5, 5, 8, 23, 5, 8, 5]);
+        ligatures.apply(&mut run);
+        let expected = [ffi, x, fi, f];
+        assert_eq!(run.iter().map(|&(id, _)| id).collect::<Vec<_>>(), expected);
+        assert_eq!(run[0].1, ligature_char(ffi));
+        assert_eq!(run[1].1, 'x');
+        assert_eq!(run[3].1, 'f');
+    }
+
+    #[test]
+    fn later_lookups_see_the_ligatures_of_earlier_ones(){
+        let (f, i, fi, fin) = (GlyphId(5), GlyphId(8), GlyphId(100), GlyphId(101));
+        let ligatures = Ligatures {
+            lookups: vec![
+                HashMap::from([(f, vec![(vec![i], fi)])]),
+                HashMap::from([(fi, vec![(vec![GlyphId(13)], fin)])]),
+            ],
+        };
+        let mut run = glyphs(&[5, 8, 13]);
+        ligatures.apply(&mut run);
+        assert_eq!(run, vec![(fin, ligature_char(fin))]);
+    }
+}
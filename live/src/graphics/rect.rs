@@ -239,6 +239,60 @@ impl Rect{
         self.max.y - self.min.y
     }
 
+    /// Infinite rect that contains every point.
+    pub const EVERYTHING: Self = Self {
+        min: Vec2::splat(-f32::INFINITY),
+        max: Vec2::INFINITY,
+    };
+
+    /// `true` if the rect has a positive width and height.
+    #[inline(always)]
+    pub fn is_positive(&self) -> bool {
+        self.min.x < self.max.x && self.min.y < self.max.y
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, p: Pos2) -> bool {
+        self.min.x <= p.x && p.x <= self.max.x && self.min.y <= p.y && p.y <= self.max.y
+    }
+
+    /// Does this rect overlap `other`? Touching edges do not count.
+    #[inline(always)]
+    pub fn intersects(&self, other: Rect) -> bool {
+        self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+    }
+
+    /// The overlap of both rects, not positive if they don't intersect.
+    #[must_use]
+    pub fn intersect(self, other: Rect) -> Self {
+        Rect::from_min_max(
+            pos2(self.min.x.max(other.min.x), self.min.y.max(other.min.y)),
+            pos2(self.max.x.min(other.max.x), self.max.y.min(other.max.y)),
+        )
+    }
+
+    /// The smallest rect containing both rects.
+    #[must_use]
+    pub fn union(self, other: Rect) -> Self {
+        Rect::from_min_max(
+            pos2(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            pos2(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        )
+    }
+
+    #[must_use]
+    pub fn translate(self, amnt: Vec2) -> Self {
+        Rect::from_min_size(self.min + amnt, self.size())
+    }
+
+    #[must_use]
+    pub fn expand(self, amnt: f32) -> Self {
+        self.shrink(-amnt)
+    }
+
 
 
 }
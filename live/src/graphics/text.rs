@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ab_glyph::{Font as _, PxScale, ScaleFont as _};
+
+use super::*;
+
+/// A glyph placed by `Assets::shape`.
+#[derive(Clone, Copy)]
+pub struct ShapedGlyph{
+    pub font: FontId,
+    /// The atlas codepoint, one standing for the glyph if it is a ligature.
+    pub c: char,
+    /// Offset from the start of the text.
+    pub x: f32,
+}
+
+/// Text laid out for drawing, measured from the same positions it is drawn at.
+pub struct ShapedText{
+    pub glyphs: Vec<ShapedGlyph>,
+    pub size: Vec2,
+}
+
+/// Entries past this are dropped wholesale rather than tracking their use.
+const SHAPE_CACHE_LIMIT: usize = 1 << 16;
+
+/// Shaped strings of a `FontChain`, by text size.
+#[derive(Default)]
+pub struct ShapeCache{
+    generation: u64,
+    len: usize,
+    by_size: HashMap<u32, HashMap<String, Rc<ShapedText>>>,
+}
+
+impl Assets {
+    /// `shape_uncached`, remembering the result in the chain.
+    ///
+    /// Layout measures every atom every frame, this keeps that off the font
+    /// tables.
+    pub fn shape(&self, chain: &FontChain, text: &str, size: f32) -> Rc<ShapedText>{
+        let mut cache = chain.cache.borrow_mut();
+        if cache.generation != self.generation || cache.len > SHAPE_CACHE_LIMIT {
+            *cache = ShapeCache { generation: self.generation, ..Default::default() };
+        }
+        let by_text = cache.by_size.entry(size.to_bits()).or_default();
+        if let Some(shaped) = by_text.get(text) {
+            return shaped.clone();
+        }
+        let shaped = Rc::new(self.shape_uncached(chain, text, size));
+        by_text.insert(text.to_owned(), shaped.clone());
+        cache.len += 1;
+        shaped
+    }
+
+    /// Places each glyph of `text` at `size` pixels, kerning pairs of glyphs
+    /// from the same font.
+    ///
+    /// Advances are taken from the font file rather than raylib, which drops
+    /// kerning.
+    pub fn shape_uncached(&self, chain: &FontChain, text: &str, size: f32) -> ShapedText{
+        let scale = PxScale::from(size);
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut x = 0.0;
+        let mut prev: Option<(FontId, ab_glyph::GlyphId)> = None;
+        for (font, id, c) in self.glyphs(chain, text) {
+            let face = self.face(font).as_scaled(scale);
+            if let Some((prev_font, prev_id)) = prev {
+                if prev_font == font {
+                    x += face.kern(prev_id, id);
+                }
+            }
+            glyphs.push(ShapedGlyph { font, c, x });
+            x += face.h_advance(id);
+            prev = Some((font, id));
+        }
+        ShapedText { glyphs, size: vec2(x, size) }
+    }
+
+    /// The glyphs `text` is drawn with, with the atlas codepoint of each.
+    ///
+    /// Each char takes its glyph from the first font of the chain that has
+    /// it, and the runs of chars taking them from the same font form the
+    /// ligatures of that font.
+    pub(super) fn glyphs(&self, chain: &FontChain, text: &str) -> Vec<(FontId, ab_glyph::GlyphId, char)>{
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut run = Vec::new();
+        let mut run_font = None;
+        let mut end_run = |font: Option<FontId>, run: &mut Vec<(ab_glyph::GlyphId, char)>| {
+            if let Some(font) = font {
+                self.ligatures(font).apply(run);
+                glyphs.extend(run.drain(..).map(|(id, c)| (font, id, c)));
+            }
+        };
+        for c in text.chars() {
+            if c.is_control() || ligature::ligature_glyph(c).is_some() {
+                continue;
+            }
+            let font = self.font_for(chain, c);
+            if run_font != Some(font) {
+                end_run(run_font, &mut run);
+                run_font = Some(font);
+            }
+            run.push((self.face(font).glyph_id(c), c));
+        }
+        end_run(run_font, &mut run);
+        glyphs
+    }
+}
+
+impl<'a> DrawHandle<'a> {
+    pub fn draw_shaped(&mut self, assets: &Assets, shader: &Shader, text: &ShapedText, pos: Vec2, color: Color){
+        let mut glyphs: Vec<(char, Vec2)> = Vec::new();
+        let mut font = None;
+        for glyph in &text.glyphs {
+            if font != Some(glyph.font) && !glyphs.is_empty() {
+                self.push_glyphs(assets, shader, font.unwrap(), std::mem::take(&mut glyphs), text.size.y, color);
+            }
+            font = Some(glyph.font);
+            glyphs.push((glyph.c, pos + Vec2::RIGHT * glyph.x));
+        }
+        if let Some(font) = font {
+            self.push_glyphs(assets, shader, font, glyphs, text.size.y, color);
+        }
+    }
+
+    fn push_glyphs(&mut self, assets: &Assets, shader: &Shader, font: FontId, glyphs: Vec<(char, Vec2)>, size: f32, color: Color){
+        let font = assets.font(font).font;
+        self.list.push(Some(shader.0), Primitive::Glyphs { font, glyphs, size, color });
+    }
+}
@@ -0,0 +1,55 @@
+//! Catching panics before they reach the host.
+//!
+//! The library links its own copy of std, a panic unwinding into the host's
+//! `catch_unwind` is a foreign exception and aborts the process. So panics are
+//! caught here and handed over as text.
+
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::sync::{Mutex, PoisonError};
+
+type Hook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send>;
+
+thread_local! {
+    static REPORT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The hook `install` replaced, `None` while it isn't installed.
+static PREVIOUS: Mutex<Option<Hook>> = Mutex::new(None);
+
+/// Has panics recorded for `catch_panic`, unless they already are.
+///
+/// A library loaded in place of one with the same `State` starts out with
+/// statics of its own and no hook, so it installs its own on first use.
+pub fn install(){
+    let mut previous = PREVIOUS.lock().unwrap_or_else(PoisonError::into_inner);
+    if previous.is_none() {
+        *previous = Some(panic::take_hook());
+        panic::set_hook(Box::new(|info| {
+            let report = format!("{info}\n\n{}", Backtrace::force_capture());
+            REPORT.with(|r| *r.borrow_mut() = Some(report));
+        }));
+    }
+}
+
+/// Puts back the hook `install` replaced, a hook left behind would point into
+/// this library after it got unloaded.
+pub fn uninstall(){
+    if let Some(previous) = PREVIOUS.lock().unwrap_or_else(PoisonError::into_inner).take() {
+        panic::set_hook(previous);
+    }
+}
+
+/// Runs `f`, turning a panic into its message, and its backtrace if the hook
+/// is installed.
+pub fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String>{
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    result.map_err(|payload| {
+        REPORT.with(|r| r.borrow_mut().take()).unwrap_or_else(|| {
+            payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panicked with a non-string payload".to_owned())
+        })
+    })
+}
@@ -0,0 +1,281 @@
+//! Just enough JSON for the messages of `control`.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Deepest nesting of arrays and objects, past it the native stack would
+/// overflow.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Json{
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    /// Members in the order they were written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Self, String>{
+        let mut parser = Parser { chars: text.chars().peekable(), depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected `{c}` after the value")),
+        }
+    }
+
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json>{
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str>{
+        match self {
+            Self::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// A number that is a whole one and not negative.
+    pub fn as_usize(&self) -> Option<usize>{
+        match *self {
+            Self::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+            _ => None,
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result{
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Writes the value on a single line.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) if n.is_finite() => write!(f, "{n}"),
+            Self::Number(_) => f.write_str("null"),
+            Self::Str(s) => write_str(f, s),
+            Self::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str("]")
+            },
+            Self::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_str("}")
+            },
+        }
+    }
+}
+
+struct Parser<'a>{
+    chars: Peekable<Chars<'a>>,
+    /// Values the one being parsed is in.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self){
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String>{
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(next) if next == c => Ok(()),
+            Some(next) => Err(format!("expected `{c}`, got `{next}`")),
+            None => Err(format!("expected `{c}`, got the end")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String>{
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("values nested deeper than {MAX_DEPTH}"));
+        }
+        self.depth += 1;
+        let value = self.bare_value();
+        self.depth -= 1;
+        value
+    }
+
+    /// `value` without the check of the depth.
+    fn bare_value(&mut self) -> Result<Json, String>{
+        self.skip_whitespace();
+        let Some(&c) = self.chars.peek() else {
+            return Err("expected a value, got the end".to_owned());
+        };
+        match c {
+            '{' => {
+                self.chars.next();
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.expect('"')?;
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => {},
+                        Some('}') => return Ok(Json::Object(members)),
+                        _ => return Err("expected `,` or `}` in an object".to_owned()),
+                    }
+                }
+            },
+            '[' => {
+                self.chars.next();
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => {},
+                        Some(']') => return Ok(Json::Array(items)),
+                        _ => return Err("expected `,` or `]` in an array".to_owned()),
+                    }
+                }
+            },
+            '"' => {
+                self.chars.next();
+                self.string().map(Json::Str)
+            },
+            '-' | '0'..='9' => {
+                let mut number = String::new();
+                while let Some(c) = self.chars.next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+                    number.push(c);
+                }
+                number.parse().map(Json::Number).map_err(|_| format!("malformed number `{number}`"))
+            },
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.next_if(char::is_ascii_alphabetic) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "null" => Ok(Json::Null),
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => Err(format!("unexpected `{c}`")),
+                }
+            },
+        }
+    }
+
+    /// The rest of a string, after its opening quote.
+    fn string(&mut self) -> Result<String, String>{
+        let mut out = String::new();
+        loop {
+            match self.chars.next().ok_or("unclosed string")? {
+                '"' => return Ok(out),
+                '\\' => match self.chars.next().ok_or("unclosed string")? {
+                    'n' => out.push('\n'),
+                    't' => out.push('\t'),
+                    'r' => out.push('\r'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // a surrogate pair
+                        if (0xd800..0xdc00).contains(&code) && self.chars.next_if_eq(&'\\').is_some() {
+                            self.expect('u')?;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+                        out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    },
+                    c => out.push(c),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String>{
+        let digits: String = (0..4).filter_map(|_| self.chars.next()).collect();
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("malformed escape `\\u{digits}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips(){
+        let text = r#"{"cmd":"show","expr":["f",[1.5,-2e3]],"ok":true,"none":null,"s":"a\"b\\c\nd"}"#;
+        assert_eq!(Json::parse(text).unwrap().to_string(), text.replace("-2e3", "-2000"));
+    }
+
+    #[test]
+    fn reads_escapes(){
+        assert_eq!(Json::parse(r#""é😀\t""#).unwrap(), Json::Str("é😀\t".to_owned()));
+    }
+
+    #[test]
+    fn rejects_malformed_input(){
+        for (text, error) in [
+            ("", "expected a value, got the end"),
+            ("[1, 2", "expected `,` or `]` in an array"),
+            (r#"{"a" 1}"#, "expected `:`, got `1`"),
+            (r#"{"a": 1,}"#, "expected `\"`, got `}`"),
+            ("\"abc", "unclosed string"),
+            ("nope", "unexpected `n`"),
+            ("1 2", "unexpected `2` after the value"),
+            ("--1", "malformed number `--1`"),
+            (r#""\uzz""#, "malformed escape `\\uzz\"`"),
+        ] {
+            assert_eq!(Json::parse(text).unwrap_err(), error, "parsing {text:?}");
+        }
+    }
+
+    #[test]
+    fn limits_nesting(){
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(Json::parse(&nested(MAX_DEPTH + 1)).unwrap_err(), format!("values nested deeper than {MAX_DEPTH}"));
+        assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+}
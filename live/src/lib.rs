@@ -1,34 +1,219 @@
+use std::collections::HashSet;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::rc::Rc;
 
+use document::*;
 use graphics::*;
 use needed_space::*;
+use persist::Persist;
 
+mod abi;
+mod config;
+mod control;
+mod eval;
+mod folds;
+mod guard;
 mod iterer;
+mod json;
+mod macros;
 mod needed_space;
+mod panels;
+mod persist;
+mod reader;
 mod renderfns;
 mod tree;
 mod se_rendering;
+mod socket;
+mod source;
+mod repl;
+mod stepper;
+pub mod document;
 pub mod graphics;
+pub mod remote;
+pub mod watch;
 
 pub struct State<'g>{
     g: &'g mut Graphics,
-    sdf_shader: Shader,
-    font: PrettyFont,
+    assets: Assets,
+    sdf_shader: ShaderId,
+    fonts: FontChain,
+    palette: config::Palette,
+    /// Message from the host drawn over everything, e.g. compiler errors.
+    overlay: Option<String>,
+    /// `Assets::error` as of the last frame, shown in the overlay.
+    asset_error: Option<String>,
+    session: Session,
+    /// Last evaluation of the document, `None` before the first.
+    run: Option<eval::Run>,
+    /// Set while stepping through an evaluation instead of showing the document.
+    stepper: Option<stepper::Stepper>,
+    /// Set while showing the macro calls of the document expanded.
+    macro_view: Option<macros::MacroView>,
+    /// Interpreter of the REPL panel, kept with its entries while the panel is
+    /// closed.
+    repl: repl::Repl,
+    repl_open: bool,
+    /// The external Lisp forms are sent to.
+    remote: remote::Remote,
+    /// `library_id` of the library that last updated the state.
+    library: usize,
+    /// Socket other programs control the viewer through, if configured.
+    control: Option<control::Control>,
+    /// The file shown, if one was given on the command line or opened
+    /// through `control`.
+    source: Option<source::Source>,
+    focus: Option<control::Focus>,
+    /// Lists of the document shown as their head only.
+    folds: folds::Folds,
 }
 
+/// The document and the camera, carried over when the layout changes.
+struct Session{
+    doc: Document,
+    camera: Vec2,
+}
+impl Session {
+    fn new() -> Self{
+        Self { doc: Document::new(sample_document()), camera: Vec2::ZERO }
+    }
+}
+impl Persist for Session {
+    fn save(&self, w: &mut persist::Writer) {
+        self.doc.save(w);
+        self.camera.save(w);
+    }
+    fn load(r: &mut persist::Reader) -> Option<Self> {
+        Some(Self {
+            doc: Document::load(r)?,
+            camera: Vec2::load(r)?,
+        })
+    }
+}
+
+/// Changes whenever `State`'s layout may have, with any change to the
+/// definitions it is made of or to the compiler.
+///
+/// The host must not hand its `State` to a library that disagrees on this
+/// value. It migrates through `save_state` and `load_state` instead.
+pub const STATE_ABI: u64 = abi::SOURCE_HASH;
 
 #[no_mangle]
-pub fn init(g: &mut Graphics) -> State{
-    let font_data = FileData::load("DejaVuSansMono.ttf").unwrap();
-        
-    let font = g.font_from_file(&font_data);
+pub fn abi_hash() -> u64{
+    STATE_ABI
+}
 
-    let shader = g.load_shader(None, "sdf.fs");
-    State {
+/// Shows `file` if given, the sample otherwise.
+///
+/// Fails if the fonts or the shader can't be loaded, or `file` can't be read.
+#[no_mangle]
+pub fn init<'g>(g: &'g mut Graphics, file: Option<&Path>) -> Result<Box<State<'g>>, String>{
+    guard::install();
+    let state = new_state(g, file);
+    if state.is_err() {
+        // the host goes on without this library
+        guard::uninstall();
+    }
+    state
+}
+
+fn new_state<'g>(g: &'g mut Graphics, file: Option<&Path>) -> Result<Box<State<'g>>, String>{
+    let mut assets = Assets::new();
+    let config = config::Config::load(&assets);
+    let fonts = assets.load_font_chain(g, &config.fonts)?;
+    let shader = assets.load_shader(g, "sdf.fs")?;
+    let control = config.control.and_then(|path| match control::Control::bind(path.as_ref()) {
+        Ok(control) => Some(control),
+        Err(e) => {
+            eprintln!("could not listen for control commands on {path}: {e}");
+            None
+        },
+    });
+    let mut session = Session::new();
+    let source = match file {
+        Some(path) => Some(source::Source::open(path.to_owned(), &mut session.doc)?),
+        None => None,
+    };
+    Ok(Box::new(State {
         g,
-        font,
+        assets,
+        fonts,
+        palette: config.palette,
         sdf_shader: shader,
+        overlay: None,
+        asset_error: None,
+        session,
+        run: None,
+        stepper: None,
+        macro_view: None,
+        repl: repl::Repl::new(),
+        repl_open: false,
+        remote: remote::Remote::new(config.remote),
+        library: library_id(),
+        control,
+        source,
+        focus: None,
+        folds: folds::Folds::new(),
+    }))
+}
+
+/// Drops a state created by `init`, with the layout of this library, and puts
+/// back the panic hook.
+#[no_mangle]
+pub fn drop_state(state: Box<State>){
+    drop(state);
+    guard::uninstall();
+}
+
+/// Saves the document and the camera, the file followed, the folds and the
+/// focus, and the REPL's entries and inputs.
+///
+/// The rest is rebuilt or dropped by the new library: the results of the
+/// last run, the stepper and the macro view are closed, the REPL's
+/// definitions are gone, the external Lisp and the control socket are
+/// connected to again, and the fonts, shader and config are loaded again.
+#[no_mangle]
+pub fn save_state(state: &State) -> Vec<u8>{
+    let mut w = persist::Writer::new();
+    state.session.save(&mut w);
+    let path = state.source.as_ref().map(|source| source.path.to_string_lossy().into_owned());
+    path.save(&mut w);
+    state.folds.save(&mut w);
+    state.focus.save(&mut w);
+    state.repl.save(&mut w);
+    state.repl_open.save(&mut w);
+    w.finish()
+}
+
+/// Restores what `save_state` saved, possibly from a different build.
+#[no_mangle]
+pub fn load_state(state: &mut State, bytes: &[u8]) -> Result<(), String>{
+    guard::install();
+    let corrupt = "saved state is truncated or corrupt";
+    let mut r = persist::Reader::new(bytes)?;
+    state.session = Session::load(&mut r).ok_or(corrupt)?;
+    let path = Option::<String>::load(&mut r).ok_or(corrupt)?;
+    state.folds = folds::Folds::load(&mut r).ok_or(corrupt)?;
+    state.focus = Option::load(&mut r).ok_or(corrupt)?;
+    state.repl = repl::Repl::load(&mut r).ok_or(corrupt)?;
+    state.repl_open = bool::load(&mut r).ok_or(corrupt)?;
+    // read again, it may have changed in the meantime
+    let saved = state.session.doc.root().clone();
+    if let Some(path) = path {
+        match source::Source::open(path.into(), &mut state.session.doc) {
+            Ok(source) => state.source = Some(source),
+            Err(e) => eprintln!("stopped following the file shown: {e}"),
+        }
+    }
+    // the folds and the focus are dropped with the next frame otherwise
+    let doc = &state.session.doc;
+    if *doc.root() == saved {
+        state.folds.follow(doc, |path| Some(path.to_vec()));
+        if let Some(focus) = &mut state.focus {
+            focus.revision = doc.revision();
+        }
     }
+    Ok(())
 }
 
 #[no_mangle]
@@ -36,24 +221,62 @@ pub fn should_close(state: &mut State) -> bool{
     state.g.window_should_close()
 }
 
+/// Layout of a node.
+///
+/// Children are shared so that retained layouts can be reused by their
+/// parents without copying.
+#[derive(Clone)]
 pub struct Tree<T>{
     value: T,
-    children: Vec<Tree<T>>,
+    children: Vec<Rc<Tree<T>>>,
 }
 impl <T> Tree<T>{
     fn leaf(value: T) -> Self{
         Self { value, children: vec![] }
     }
     fn new(value: T, children: Vec<Tree<T>>) -> Self{
-        Self{value, children}
+        Self{value, children: children.into_iter().map(Rc::new).collect()}
     }
 }
 
 pub struct Ui<'a, 'b, 'c>{
     g: &'c mut DrawHandle<'a>,
-    font: PrettyFont,
+    assets: &'b Assets,
+    fonts: &'b FontChain,
     sdf_shader: &'b Shader,
     text_scale: f32,
+    /// Retained layouts of the document being drawn.
+    layout_cache: Option<&'b LayoutCache>,
+    /// Nodes outside of this rect are not drawn.
+    visible: Rect,
+    /// Results shown next to the top-level forms.
+    run: Option<&'b eval::Run>,
+    /// Node marked as the one being evaluated.
+    highlight: Option<*const Expr>,
+    /// Nodes marked as linked to the hovered one.
+    linked: Option<&'b HashSet<*const Expr>>,
+    /// `None` if the mouse is not over the document.
+    mouse: Option<Vec2>,
+    /// Innermost node drawn under the mouse so far.
+    hovered: Option<*const Expr>,
+    /// Where the `highlight` node was drawn.
+    highlighted: Option<Rect>,
+    /// Lists drawn folded.
+    folded: &'b HashSet<*const Expr>,
+    /// Colors of the boxes of lists.
+    palette: &'b config::Palette,
+    /// How many lists the node being drawn is in.
+    depth: usize,
+}
+impl<'a, 'b, 'c> Ui<'a, 'b, 'c> {
+    /// Size of `text` drawn with `paint_text`.
+    fn text_size(&self, text: &str) -> Vec2{
+        self.assets.shape(self.fonts, text, self.text_scale).size
+    }
+    fn paint_text(&mut self, text: &str, pos: Vec2, color: Color){
+        let shaped = self.assets.shape(self.fonts, text, self.text_scale);
+        self.g.draw_shaped(self.assets, self.sdf_shader, &shaped, pos, color);
+    }
 }
 impl<'a, 'b, 'c> Deref for Ui<'a, 'b, 'c> {
     type Target = DrawHandle<'a>;
@@ -68,6 +291,7 @@ impl<'a, 'b, 'c> DerefMut for Ui<'a, 'b, 'c> {
     }
 }
 
+#[derive(Clone, PartialEq)]
 pub enum Expr{
     Atom(String),
     List(Vec<Expr>),
@@ -85,28 +309,308 @@ impl From<&Expr> for String {
         }
     }
 }
-macro_rules! sexpr {
-    (($($i: tt)*)) => {
-        Expr::List(vec![$(sexpr!($i)),*])
-    };
-    ($i: ident) => {
-        Expr::Atom(stringify!($i).into())
-    };
-    ($i: expr) => {
-        Expr::Atom($i.into())
-    };
+impl Expr {
+    /// The children of a list, an atom on its own.
+    pub fn forms(&self) -> &[Expr]{
+        match self {
+            Expr::Atom(_) => std::slice::from_ref(self),
+            Expr::List(lst) => lst,
+        }
+    }
+    /// The node at `path` below this one.
+    pub fn at(&self, path: &[usize]) -> Option<&Expr>{
+        path.iter().try_fold(self, |node, &i| match node {
+            Expr::List(children) => children.get(i),
+            Expr::Atom(_) => None,
+        })
+    }
+    fn for_each_atom(&self, f: &mut impl FnMut(&str)){
+        match self {
+            Expr::Atom(s) => f(s),
+            Expr::List(children) => children.iter().for_each(|c| c.for_each_atom(f)),
+        }
+    }
+}
+const SAMPLE: &str = r#"
+(defun fizbuz (zahl)
+  (loop for x from 1 to zahl do
+    (if (= 0 (mod x 15))
+      (print "fizbuz")
+      (if (= 0 (mod x 3))
+        (print "fiz")
+        (if (= 0 (mod x 5))
+          (print "buzz")
+          (print x))))))
+
+(fizbuz 15)
+"#;
+
+/// Top-level forms shown until something else is opened.
+fn sample_document() -> Expr{
+    Expr::List(reader::read_all(SAMPLE).expect("the sample is well formed"))
 }
 
 
+#[no_mangle]
+pub fn set_overlay(state: &mut State, text: Option<String>){
+    state.overlay = text;
+}
+
+fn draw_overlay(ui: &mut DrawHandle, text: &str){
+    ui.set_layer(Layer::Overlay);
+    let font_size = 10;
+    let line_height = 12;
+    let screen = Rect::from_min_size(Vec2::ZERO, ui.screen_size());
+    ui.draw_rect(screen, Color{ r: 0, g: 0, b: 0, a: 200 });
+    for (i, line) in text.lines().enumerate() {
+        ui.draw_text(line, 10, 30 + i as i32 * line_height, font_size, colors::RED);
+    }
+}
+
+/// Draws a frame showing nothing but the overlay.
+///
+/// Used by the host while `update` is known to panic.
+#[no_mangle]
+pub fn draw_overlay_frame(state: &mut State){
+    let overlay = state.overlay.as_deref().unwrap_or("");
+    state.g.draw_frame(|ui|{
+        ui.clear_background(colors::BLACK);
+        draw_overlay(ui, overlay);
+    });
+}
+
 #[no_mangle]
 pub fn should_reload(state: &mut State)->bool{
-    state.g.is_key_pressed(Key::R)
+    // typed into the REPL instead
+    !state.repl_open && state.g.is_key_pressed(Key::R)
+}
+
+const SCROLL_SPEED: f32 = 40.0;
+const RUN_KEY: Key = Key::F5;
+const STEP_KEY: Key = Key::F6;
+const MACRO_KEY: Key = Key::F7;
+const REPL_KEY: Key = Key::F8;
+/// Sends the top-level form under the mouse to the external Lisp.
+const SEND_KEY: Key = Key::F9;
+/// Folds or unfolds the list under the mouse, or the focused one. Clicking
+/// a list does too.
+const FOLD_KEY: Key = Key::Space;
+/// Fold the document to as many levels as the digit, `Zero` unfolds all.
+const DEPTH_KEYS: [Key; 10] = [
+    Key::Zero, Key::One, Key::Two, Key::Three, Key::Four,
+    Key::Five, Key::Six, Key::Seven, Key::Eight, Key::Nine,
+];
+
+/// `update`, with a panic returned as its message and backtrace.
+#[no_mangle]
+pub fn try_update(state: &mut State) -> Result<(), String>{
+    guard::install();
+    guard::catch_panic(|| update(state))
+}
+
+/// Moves through the steps with the arrow keys or by dragging the slider.
+fn step_input(g: &Graphics, stepper: &mut stepper::Stepper, slider: Rect){
+    let current = stepper.current();
+    if g.is_key_pressed(Key::Right) {
+        stepper.seek(current + 1);
+    }
+    if g.is_key_pressed(Key::Left) {
+        stepper.seek(current.saturating_sub(1));
+    }
+    if g.is_key_pressed(Key::Home) {
+        stepper.seek(0);
+    }
+    if g.is_key_pressed(Key::End) {
+        stepper.seek(usize::MAX);
+    }
+    let mouse = g.mouse_position();
+    if g.is_mouse_button_down(MouseButton::Left) && slider.contains(mouse) {
+        let step = panels::history_step(slider, mouse.x, stepper.len());
+        if step != stepper.current() {
+            stepper.seek(step);
+        }
+    }
+}
+
+/// How far below the top of the document the top-level form `form` of
+/// `layout` starts.
+fn form_top(layout: &Tree<NeededSpace>, form: usize) -> Option<f32>{
+    let above = layout.children.get(..form)?;
+    Some(above.iter().map(|child| child.value.size().y).sum())
+}
+
+/// The top-level form of `layout`, drawn from `top` down, at `view_top` and
+/// how far into the form that is.
+fn top_form(layout: &Tree<NeededSpace>, top: f32, view_top: f32) -> Option<(usize, f32)>{
+    let mut y = top;
+    for (i, child) in layout.children.iter().enumerate() {
+        let height = child.value.size().y;
+        if y + height > view_top {
+            return Some((i, view_top - y));
+        }
+        y += height;
+    }
+    None
+}
+
+/// Differs between the copies of this library the host loads.
+fn library_id() -> usize{
+    static ID: u8 = 0;
+    &ID as *const u8 as usize
 }
 
 #[no_mangle]
 pub fn update(state: &mut State) {
-    let font = state.font;
-    let sdf_shader = &state.sdf_shader;
+    // values of the interpreters point to the builtins of the library that
+    // made them, the previous one is unloaded after a reload
+    let reloaded = state.library != library_id();
+    state.library = library_id();
+    if reloaded {
+        state.repl.reset();
+        if let Some(stepper) = &mut state.stepper {
+            let current = stepper.current();
+            *stepper = stepper::Stepper::new(&state.session.doc);
+            stepper.seek(current);
+        }
+    }
+    // the file shown was saved by another editor, stay on the same forms
+    let mut restore_anchor = None;
+    if let Some(source) = &mut state.source {
+        if let Some(before) = source.poll(&mut state.session.doc) {
+            let doc = &state.session.doc;
+            state.focus = state.focus.take().and_then(|focus| {
+                let path = source.follow(&before, doc, &focus.path)?;
+                Some(control::Focus { path, revision: doc.revision(), ..focus })
+            });
+            source.anchor = source.anchor.and_then(|(form, offset)| {
+                Some((*source.follow(&before, doc, &[form])?.first()?, offset))
+            });
+            restore_anchor = source.anchor;
+            state.folds.follow(doc, |path| source.follow(&before, doc, path));
+        }
+    }
+    state.folds.sync(&state.session.doc);
+    // once run, the document is evaluated again after every edit
+    let stale = state.run.as_ref().is_some_and(|run| reloaded || run.revision != state.session.doc.revision());
+    if stale || state.g.is_key_pressed(RUN_KEY) {
+        state.run = Some(eval::Run::new(&state.session.doc));
+    }
+    // the stepper and the macro view replace the document, only one at a time
+    if state.g.is_key_pressed(STEP_KEY) && state.stepper.take().is_none() {
+        state.stepper = Some(stepper::Stepper::new(&state.session.doc));
+        state.macro_view = None;
+    }
+    if state.g.is_key_pressed(MACRO_KEY) && state.macro_view.take().is_none() {
+        state.macro_view = Some(macros::MacroView::new(&state.session.doc));
+        state.stepper = None;
+    }
+    if state.macro_view.as_ref().is_some_and(|m| m.revision != state.session.doc.revision()) {
+        state.macro_view = Some(macros::MacroView::new(&state.session.doc));
+    }
+    if state.g.is_key_pressed(REPL_KEY) {
+        state.repl_open = !state.repl_open;
+    }
+    if state.repl_open {
+        state.repl.handle_input(state.g);
+    }
+    for reply in state.remote.poll() {
+        state.repl.push_reply(reply);
+    }
+    let send = state.g.is_key_pressed(SEND_KEY);
+    let click = state.g.is_mouse_button_pressed(MouseButton::Left);
+    let mut fold = false;
+    // typed into the REPL instead
+    if !state.repl_open {
+        fold = state.g.is_key_pressed(FOLD_KEY);
+        if let Some(depth) = DEPTH_KEYS.iter().position(|&key| state.g.is_key_pressed(key)) {
+            state.folds.unfold_to_depth(&state.session.doc, Some(depth).filter(|&d| d != 0));
+        }
+    }
+    let screen = Rect::from_min_size(Vec2::ZERO, state.g.screen_size());
+    let dock = panels::dock(screen, state.run.is_some(), state.stepper.is_some(), state.repl_open);
+    if let Some(stepper) = &mut state.stepper {
+        if stepper.revision != state.session.doc.revision() {
+            let current = stepper.current();
+            *stepper = stepper::Stepper::new(&state.session.doc);
+            stepper.seek(current);
+        }
+        step_input(state.g, stepper, dock.history.unwrap());
+    }
+    if state.focus.as_ref().is_some_and(|f| f.revision != state.session.doc.revision()) {
+        state.focus = None;
+    }
+    let focused = state.focus.as_ref().and_then(|f| state.session.doc.get(&f.path));
+    // the stepper shows its copy of the document with the values filled in
+    let (doc, highlight) = match (&state.stepper, &state.macro_view) {
+        (Some(stepper), _) => (&stepper.view, stepper.highlight().map(|e| e as *const Expr)),
+        (_, Some(macro_view)) => (&macro_view.view, None),
+        (None, None) => (&state.session.doc, focused.map(|e| e as *const Expr)),
+    };
+    // the view is kept on a form of the file across the times it is read
+    let anchored = state.source.is_some() && state.stepper.is_none() && state.macro_view.is_none();
+    let notice = state.source.as_ref().and_then(|source| source.error.as_deref());
+    // drawn all at once to learn where the focused node is, even off screen
+    let find_focus = highlight.is_some() && state.focus.as_ref().is_some_and(|f| !f.scrolled);
+    let linked = state.macro_view.as_ref().map(|m| &m.linked);
+    let top_level = match state.macro_view {
+        Some(_) => renderfns::ROWS,
+        None => renderfns::TOP_LEVEL,
+    };
+    let mouse = Some(state.g.mouse_position()).filter(|&m| dock.content.contains(m));
+    // folds are kept for the document, not the views replacing it
+    let showing_document = state.stepper.is_none() && state.macro_view.is_none();
+    let folded = if showing_document { state.folds.nodes(&state.session.doc) } else { HashSet::new() };
+
+    doc.root().for_each_atom(&mut |atom| state.assets.request_glyphs(&state.fonts, atom));
+    if let Some(run) = &state.run {
+        for line in &run.interpreter.output {
+            state.assets.request_glyphs(&state.fonts, line);
+        }
+        for form in doc.forms() {
+            if let Some((text, _)) = renderfns::result_annotation(run, form) {
+                state.assets.request_glyphs(&state.fonts, &text);
+            }
+        }
+    }
+    if let Some(text) = notice {
+        state.assets.request_glyphs(&state.fonts, text);
+    }
+    if !folded.is_empty() {
+        state.assets.request_glyphs(&state.fonts, renderfns::FOLDED_GLYPHS);
+    }
+    if state.repl_open {
+        state.repl.history.root().for_each_atom(&mut |atom| state.assets.request_glyphs(&state.fonts, atom));
+        state.assets.request_glyphs(&state.fonts, &format!("> {}", state.repl.input));
+    }
+    for e in state.assets.poll(state.g) {
+        eprintln!("{e}");
+    }
+    // the overlay is left alone if the host replaced the error in it
+    let asset_error = state.assets.error();
+    if asset_error != state.asset_error {
+        if asset_error.is_some() || state.overlay == state.asset_error {
+            state.overlay = asset_error.clone();
+        }
+        state.asset_error = asset_error;
+    }
+    let assets = &state.assets;
+    let fonts = &state.fonts;
+    let palette = &state.palette;
+    let sdf_shader = state.assets.shader(state.sdf_shader);
+    let overlay = state.overlay.as_deref();
+    let run = state.run.as_ref();
+    let stepper = state.stepper.as_ref();
+    let wheel = state.g.mouse_wheel_move() * SCROLL_SPEED;
+    match dock.repl {
+        Some(rect) if rect.contains(state.g.mouse_position()) => state.repl.scroll += wheel,
+        _ => state.session.camera.y -= wheel,
+    }
+    let repl = state.repl_open.then_some(&state.repl);
+    let mut camera = state.session.camera;
+    let mut anchor = None;
+    let mut hovered = None;
+    let mut highlighted = None;
+    let mut max_scroll = 0.0;
     state.g.draw_frame(|ui|{
         ui.clear_background(colors::BLACK);
         ui.draw_fps(10, 10);
@@ -114,38 +618,97 @@ pub fn update(state: &mut State) {
 
         let mut ui = Ui{
             g: ui,
-            font,
+            assets,
+            fonts,
             sdf_shader: &sdf_shader,
             text_scale,
+            layout_cache: Some(&doc.layout),
+            visible: if find_focus { Rect::EVERYTHING } else { dock.content },
+            run,
+            highlight,
+            linked,
+            mouse,
+            hovered: None,
+            highlighted: None,
+            folded: &folded,
+            palette,
+            depth: 0,
         };
 
-        let test = sexpr!(
-            (defun fizbuz (zahl)
-            (loop for x from "1" to zahl do
-                (if ("=" "0" (mod x "15"))
-                (print "fizbuz")
-                (if ("=" "0" (mod x "3"))
-                    (print "fiz")
-                    (if ("=" "0" (mod x "5"))
-                    (print "buzz")
-                    (print x))))))
-        );
-
+        doc.layout.reset_if_changed(assets.generation(), text_scale);
+        let expr = doc.root();
 
-        let expr = sexpr!(
+        let size_tree = (top_level.layout)(expr, &ui);
 
-            (define (f x)
-             (if ("=" x "5")
-                (display x)
-                (display ("+" x "5"))))
+        let origin = vec2(50.0, 50.0);
+        if anchored {
+            if let Some(top) = restore_anchor.and_then(|(form, offset)| Some(form_top(&size_tree, form)? + offset)) {
+                camera.y = origin.y + top - dock.content.top();
+            }
+            anchor = top_form(&size_tree, origin.y - camera.y, dock.content.top());
+        }
+        let rect = Rect::from_min_size(origin - camera, size_tree.value.size());
+        (top_level.draw)(expr, &mut ui, rect, &size_tree);
+        hovered = ui.hovered;
+        highlighted = ui.highlighted;
 
-        );
-        let expr = test;
+        if let (Some(rect), Some(run)) = (dock.output, run) {
+            panels::draw_output(&mut ui, rect, &run.interpreter.output);
+        }
+        if let (Some(rect), Some(stepper)) = (dock.history, stepper) {
+            panels::draw_history(&mut ui, rect, stepper.current(), stepper.len(), stepper.truncated());
+        }
+        if let (Some(rect), Some(repl)) = (dock.repl, repl) {
+            max_scroll = panels::draw_repl(&mut ui, rect, repl);
+        }
 
-        let rfn = expr.render_fn();
-        let size_tree = (rfn.layout)(&expr, &ui);
+        if let Some(text) = notice {
+            panels::draw_notice(&mut ui, dock.content, text);
+        }
 
-        let rect = Rect::from_min_size(vec2(50.0, 50.0), size_tree.value.size());
-        (rfn.draw)(&expr, &mut ui, rect, &size_tree);
+        if let Some(text) = overlay {
+            draw_overlay(ui.g, text);
+        }
     });
+    state.session.camera = camera;
+    if let Some(source) = &mut state.source {
+        source.anchor = anchor;
+    }
+    // the answer shows up in the REPL's history
+    let sent = hovered
+        .filter(|_| send)
+        .and_then(|node| doc.path_of(node))
+        .and_then(|path| doc.forms().get(*path.first()?).cloned());
+    if let Some(form) = sent {
+        state.repl_open = true;
+        if let Err(e) = state.remote.send(&form) {
+            state.repl.push_reply(remote::Reply { form, printed: Vec::new(), result: Err(e) });
+        }
+    }
+    let to_fold = match (click, fold) {
+        _ if !showing_document => None,
+        (true, _) => hovered.and_then(|node| doc.path_of(node)),
+        (_, true) => hovered
+            .and_then(|node| doc.path_of(node))
+            .or_else(|| state.focus.as_ref().map(|focus| focus.path.clone())),
+        (false, false) => None,
+    };
+    if let Some(path) = to_fold.and_then(|path| folds::foldable(doc, path)) {
+        state.folds.toggle(doc, &path);
+    }
+    if let Some(macro_view) = &mut state.macro_view {
+        macro_view.set_hovered(hovered);
+    }
+    state.repl.scroll = state.repl.scroll.clamp(0.0, max_scroll);
+    if let (true, Some(focus)) = (find_focus, &mut state.focus) {
+        if let Some(rect) = highlighted {
+            state.session.camera.y += rect.center().y - dock.content.center().y;
+        }
+        focus.scrolled = true;
+    }
+    // after drawing, for screenshots of the frame just drawn
+    if let Some(mut control) = state.control.take() {
+        control.poll(|command| control::run(state, command));
+        state.control = Some(control);
+    }
 }
@@ -0,0 +1,122 @@
+//! Shows the macro calls of the document beside their expansions.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::Expr;
+use crate::document::Document;
+use crate::eval::{Expansion, Interpreter, Origins};
+
+pub struct MacroView{
+    /// `Document::revision` the expansions were made at.
+    pub revision: u64,
+    /// A row per macro call, outer calls first: the call, its expansion by
+    /// one step and its full expansion.
+    pub view: Document,
+    /// Nodes of the expansions in `view`, with the node of the call they are
+    /// copies of.
+    links: HashMap<*const Expr, *const Expr>,
+    /// The node hovered in the last frame and the nodes linked to it, empty
+    /// if it has none.
+    pub linked: HashSet<*const Expr>,
+}
+
+fn atom(text: &str) -> Expr{
+    Expr::Atom(text.to_owned())
+}
+
+fn error(e: String) -> Expr{
+    Expr::Atom(format!("error: {e}"))
+}
+
+impl MacroView {
+    /// Expands the macro calls anywhere in the forms of `doc`, with the macros
+    /// it defines at the top level.
+    pub fn new(doc: &Document) -> Self{
+        let mut interpreter = Interpreter::new();
+        let mut rows = Vec::new();
+        if let Err(e) = interpreter.define_macros(doc.forms()) {
+            rows.push(Expr::List(vec![error(e)]));
+        }
+        let mut calls = Vec::new();
+        for form in doc.forms() {
+            macro_calls(&mut interpreter, form, &mut calls);
+        }
+        // the row, the column of an expansion in it and its origins
+        let mut expansions: Vec<(usize, usize, Origins)> = Vec::new();
+        for (call, once) in calls {
+            let row = match once {
+                Ok(once) => {
+                    expansions.push((rows.len(), 2, once.origins));
+                    let full = match interpreter.expand_all(call) {
+                        Ok(full) => {
+                            expansions.push((rows.len(), 4, full.origins));
+                            full.form
+                        },
+                        Err(e) => error(e),
+                    };
+                    vec![call.clone(), atom("expand-1:"), once.form, atom("expand:"), full]
+                },
+                Err(e) => vec![call.clone(), error(e)],
+            };
+            rows.push(Expr::List(row));
+        }
+        if rows.is_empty() {
+            rows.push(Expr::List(vec![atom("no macro calls")]));
+        }
+        let view = Document::new(Expr::List(rows));
+        let mut links = HashMap::new();
+        for (row, column, origins) in expansions {
+            let (Some(call), Some(expansion)) = (view.get(&[row, 0]), view.get(&[row, column])) else {
+                continue;
+            };
+            for (copy, original) in origins {
+                if let (Some(copy), Some(original)) = (expansion.at(&copy), call.at(&original)) {
+                    link(&mut links, copy, original);
+                }
+            }
+        }
+        Self { revision: doc.revision(), view, links, linked: HashSet::new() }
+    }
+
+    /// Links the node of `view` at `hovered` to the nodes that are copies of
+    /// it, or that it is a copy of, for highlighting.
+    pub fn set_hovered(&mut self, hovered: Option<*const Expr>){
+        self.linked.clear();
+        let Some(hovered) = hovered else {
+            return;
+        };
+        let original = self.links.get(&hovered).copied().unwrap_or(hovered);
+        self.linked.extend(self.links.iter().filter(|&(_, &o)| o == original).map(|(&copy, _)| copy));
+        if !self.linked.is_empty() {
+            self.linked.insert(original);
+        }
+    }
+}
+
+/// Collects the macro calls in `node` with their expansion by one step, a
+/// call before the ones in its arguments. Quoted forms and the templates of
+/// `define-syntax` are not code and left out.
+fn macro_calls<'a>(interpreter: &mut Interpreter, node: &'a Expr, out: &mut Vec<(&'a Expr, Result<Expansion, String>)>){
+    let Expr::List(children) = node else {
+        return;
+    };
+    if matches!(node.car(), Some(Expr::Atom(h)) if h == "quote" || h == "quasiquote" || h == "define-syntax") {
+        return;
+    }
+    match interpreter.expand_1(node) {
+        Ok(Some(once)) => out.push((node, Ok(once))),
+        Ok(None) => {},
+        Err(e) => out.push((node, Err(e))),
+    }
+    for child in children {
+        macro_calls(interpreter, child, out);
+    }
+}
+
+/// Links the nodes of `copy` to the ones of `original` they copy.
+fn link(links: &mut HashMap<*const Expr, *const Expr>, copy: &Expr, original: &Expr){
+    links.insert(copy, original);
+    if let (Expr::List(copy), Expr::List(original)) = (copy, original) {
+        copy.iter().zip(original).for_each(|(c, o)| link(links, c, o));
+    }
+}
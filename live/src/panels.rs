@@ -0,0 +1,156 @@
+//! Panels docked to the edges of the window, drawn over the document.
+
+use crate::Ui;
+use crate::graphics::*;
+use crate::renderfns;
+use crate::repl::Repl;
+
+pub const OUTPUT_HEIGHT: f32 = 150.0;
+const PANEL_PADDING: f32 = 6.0;
+const PANEL_BACKGROUND: Color = Color{ r: 20, g: 20, b: 24, a: 255 };
+const LINE_SPACING: f32 = 1.2;
+
+/// Fills `rect` as the background of a panel.
+fn background(ui: &mut Ui, rect: Rect){
+    ui.draw_rect(rect, PANEL_BACKGROUND);
+    ui.draw_rect(Rect::from_min_size(rect.min, vec2(rect.width(), 1.0)), colors::DARKGRAY);
+}
+
+/// Draws the lines printed by the last run, the latest at the bottom.
+pub fn draw_output(ui: &mut Ui, rect: Rect, lines: &[String]){
+    ui.set_layer(Layer::Panel);
+    background(ui, rect);
+    let inner = rect.shrink(PANEL_PADDING);
+    let line_height = ui.text_scale * LINE_SPACING;
+    ui.push_clip(inner);
+    let mut pos = inner.left_bottom() + Vec2::UP * line_height;
+    for line in lines.iter().rev() {
+        if pos.y + line_height < inner.top() {
+            break;
+        }
+        ui.paint_text(line, pos, colors::LIGHTGRAY);
+        pos.y -= line_height;
+    }
+    ui.pop_clip();
+    ui.set_layer(Layer::Content);
+}
+
+const NOTICE_COLOR: Color = Color{ r: 230, g: 41, b: 55, a: 255 };
+
+/// Draws `text` in a strip along the top of `rect`, for problems the viewer
+/// carries on through.
+pub fn draw_notice(ui: &mut Ui, rect: Rect, text: &str){
+    ui.set_layer(Layer::Panel);
+    let height = ui.text_scale * LINE_SPACING + 2.0 * PANEL_PADDING;
+    let strip = Rect::from_min_size(rect.min, vec2(rect.width(), height));
+    background(ui, strip);
+    let inner = strip.shrink(PANEL_PADDING);
+    ui.push_clip(inner);
+    ui.paint_text(text, inner.min, NOTICE_COLOR);
+    ui.pop_clip();
+    ui.set_layer(Layer::Content);
+}
+
+pub const HISTORY_HEIGHT: f32 = 44.0;
+const KNOB_RADIUS: f32 = 6.0;
+
+/// The line the knob of the history slider moves along.
+fn history_track(rect: Rect) -> Rect{
+    let inner = rect.shrink(PANEL_PADDING);
+    let y = inner.bottom() - KNOB_RADIUS;
+    Rect::from_min_max(pos2(inner.left() + KNOB_RADIUS, y - 1.0), pos2(inner.right() - KNOB_RADIUS, y + 1.0))
+}
+
+/// The step of a history of `len` steps shown by the slider at `x`.
+pub fn history_step(rect: Rect, x: f32, len: usize) -> usize{
+    let track = history_track(rect);
+    let t = ((x - track.left()) / track.width()).clamp(0.0, 1.0);
+    (t * len.saturating_sub(1) as f32).round() as usize
+}
+
+/// Draws the slider over the steps of an evaluation.
+pub fn draw_history(ui: &mut Ui, rect: Rect, current: usize, len: usize, truncated: bool){
+    ui.set_layer(Layer::Panel);
+    background(ui, rect);
+    let inner = rect.shrink(PANEL_PADDING);
+    let mut label = match len {
+        0 => "nothing evaluated".to_owned(),
+        len => format!("step {} of {len}", current + 1),
+    };
+    if truncated {
+        label += ", later steps were not recorded";
+    }
+    ui.push_clip(inner);
+    ui.paint_text(&label, inner.min, colors::LIGHTGRAY);
+    ui.pop_clip();
+    let track = history_track(rect);
+    ui.draw_rect(track, colors::DARKGRAY);
+    let t = if len > 1 { current as f32 / (len - 1) as f32 } else { 0.0 };
+    let knob = pos2(track.left() + t * track.width(), track.center().y);
+    ui.draw_circle(knob.x as i32, knob.y as i32, KNOB_RADIUS, colors::LIGHTGRAY);
+    ui.set_layer(Layer::Content);
+}
+
+pub const REPL_HEIGHT: f32 = 220.0;
+const PROMPT: &str = "> ";
+const CURSOR_WIDTH: f32 = 2.0;
+
+/// Draws the entries of `repl` above its prompt, scrolled up by `repl.scroll`.
+///
+/// Returns how far the history can be scrolled.
+pub fn draw_repl<'b>(ui: &mut Ui<'_, 'b, '_>, rect: Rect, repl: &'b Repl) -> f32{
+    ui.set_layer(Layer::Panel);
+    background(ui, rect);
+    let inner = rect.shrink(PANEL_PADDING);
+    let line_height = ui.text_scale * LINE_SPACING;
+    let (entries, prompt) = inner.split_top_bottom_at_y(inner.bottom() - line_height);
+
+    // the entries are laid out like a document, with their own retained layouts
+    let saved = (ui.layout_cache, ui.visible, ui.mouse);
+    ui.layout_cache = Some(&repl.history.layout);
+    ui.visible = entries;
+    ui.mouse = None;
+    repl.history.layout.reset_if_changed(ui.assets.generation(), ui.text_scale);
+    let expr = repl.history.root();
+    let size_tree = (renderfns::ROWS.layout)(expr, ui);
+    let size = size_tree.value.size();
+    let max_scroll = (size.y - entries.height()).max(0.0);
+    let scroll = repl.scroll.clamp(0.0, max_scroll);
+    let top = entries.bottom() - size.y + scroll;
+    ui.push_clip(entries);
+    (renderfns::ROWS.draw)(expr, ui, Rect::from_min_size(pos2(entries.left(), top), size), &size_tree);
+    ui.pop_clip();
+    (ui.layout_cache, ui.visible, ui.mouse) = saved;
+
+    let text = format!("{PROMPT}{}", repl.input);
+    ui.push_clip(prompt);
+    ui.paint_text(&text, prompt.min, colors::LIGHTGRAY);
+    let cursor = Rect::from_min_size(prompt.min + Vec2::RIGHT * ui.text_size(&text).x, vec2(CURSOR_WIDTH, ui.text_scale));
+    ui.draw_rect(cursor, colors::LIGHTGRAY);
+    ui.pop_clip();
+    ui.set_layer(Layer::Content);
+    max_scroll
+}
+
+/// Where the document and the panels docked below it go.
+pub struct Dock{
+    pub content: Rect,
+    pub output: Option<Rect>,
+    pub history: Option<Rect>,
+    pub repl: Option<Rect>,
+}
+
+/// Docks the panels that are shown to the bottom of `screen`, the REPL
+/// lowest, then the output.
+pub fn dock(screen: Rect, output: bool, history: bool, repl: bool) -> Dock{
+    let mut content = screen;
+    let mut take = |height: f32| {
+        let (rest, panel) = content.split_top_bottom_at_y(content.bottom() - height);
+        content = rest;
+        panel
+    };
+    let repl = repl.then(|| take(REPL_HEIGHT));
+    let output = output.then(|| take(OUTPUT_HEIGHT));
+    let history = history.then(|| take(HISTORY_HEIGHT));
+    Dock { content, output, history, repl }
+}
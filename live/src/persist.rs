@@ -0,0 +1,228 @@
+//! Byte encoding of the state that survives a reload.
+
+use crate::Expr;
+use crate::graphics::Vec2;
+
+/// Bumped whenever the encoding changes, old bytes are rejected instead of misread.
+const FORMAT_VERSION: u32 = 4;
+const MAGIC: &[u8; 4] = b"SEE\0";
+
+pub struct Writer{
+    bytes: Vec<u8>,
+}
+impl Writer {
+    pub fn new() -> Self{
+        let mut w = Self { bytes: Vec::new() };
+        w.bytes.extend_from_slice(MAGIC);
+        w.u32(FORMAT_VERSION);
+        w
+    }
+    pub fn finish(self) -> Vec<u8>{
+        self.bytes
+    }
+    pub fn u8(&mut self, v: u8){
+        self.bytes.push(v);
+    }
+    pub fn u32(&mut self, v: u32){
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn f32(&mut self, v: f32){
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn str(&mut self, v: &str){
+        self.u32(v.len() as u32);
+        self.bytes.extend_from_slice(v.as_bytes());
+    }
+}
+
+pub struct Reader<'a>{
+    bytes: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, String>{
+        let mut r = Self { bytes };
+        if r.take(MAGIC.len()) != Some(MAGIC) {
+            return Err("not a saved state".to_owned());
+        }
+        match r.u32() {
+            Some(FORMAT_VERSION) => Ok(r),
+            Some(v) => Err(format!("saved state has format {v}, expected {FORMAT_VERSION}")),
+            None => Err("saved state is truncated".to_owned()),
+        }
+    }
+    fn take(&mut self, n: usize) -> Option<&'a [u8]>{
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(head)
+    }
+    pub fn u8(&mut self) -> Option<u8>{
+        self.take(1).map(|b| b[0])
+    }
+    pub fn u32(&mut self) -> Option<u32>{
+        self.take(4).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+    pub fn f32(&mut self) -> Option<f32>{
+        self.take(4).map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+    }
+    pub fn str(&mut self) -> Option<String>{
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+pub trait Persist: Sized {
+    fn save(&self, w: &mut Writer);
+    fn load(r: &mut Reader) -> Option<Self>;
+}
+
+impl Persist for Vec2 {
+    fn save(&self, w: &mut Writer) {
+        w.f32(self.x);
+        w.f32(self.y);
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        Some(Vec2::new(r.f32()?, r.f32()?))
+    }
+}
+
+impl Persist for f32 {
+    fn save(&self, w: &mut Writer) {
+        w.f32(*self);
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        r.f32()
+    }
+}
+
+impl Persist for usize {
+    fn save(&self, w: &mut Writer) {
+        w.u32(*self as u32);
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        r.u32().map(|v| v as usize)
+    }
+}
+
+impl Persist for bool {
+    fn save(&self, w: &mut Writer) {
+        w.u8(*self as u8);
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Persist for String {
+    fn save(&self, w: &mut Writer) {
+        w.str(self);
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        r.str()
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn save(&self, w: &mut Writer) {
+        match self {
+            None => w.u8(0),
+            Some(value) => {
+                w.u8(1);
+                value.save(w);
+            },
+        }
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(None),
+            1 => Some(Some(T::load(r)?)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn save(&self, w: &mut Writer) {
+        w.u32(self.len() as u32);
+        for item in self {
+            item.save(w);
+        }
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        let len = r.u32()?;
+        (0..len).map(|_| T::load(r)).collect()
+    }
+}
+
+impl Persist for Expr {
+    fn save(&self, w: &mut Writer) {
+        match self {
+            Expr::Atom(s) => {
+                w.u8(0);
+                w.str(s);
+            },
+            Expr::List(children) => {
+                w.u8(1);
+                children.save(w);
+            },
+        }
+    }
+    fn load(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(Expr::Atom(r.str()?)),
+            1 => Some(Expr::List(Vec::load(r)?)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Persist>(value: &T) -> Option<T>{
+        let mut w = Writer::new();
+        value.save(&mut w);
+        T::load(&mut Reader::new(&w.finish()).ok()?)
+    }
+
+    #[test]
+    fn values_round_trip(){
+        let expr = Expr::List(vec![Expr::Atom("f".to_owned()), Expr::List(vec![]), Expr::Atom("\"é\"".to_owned())]);
+        assert!(round_trip(&expr) == Some(expr));
+        assert!(round_trip(&Vec2::new(1.5, -2.0)) == Some(Vec2::new(1.5, -2.0)));
+        assert_eq!(round_trip(&Some("a".to_owned())), Some(Some("a".to_owned())));
+        assert_eq!(round_trip(&None::<String>), Some(None));
+        assert_eq!(round_trip(&vec!["a".to_owned(), String::new()]), Some(vec!["a".to_owned(), String::new()]));
+        assert_eq!(round_trip(&vec![vec![0usize, 7], vec![]]), Some(vec![vec![0, 7], vec![]]));
+        assert_eq!(round_trip(&true), Some(true));
+    }
+
+    #[test]
+    fn rejects_other_formats(){
+        let mut bytes = Writer::new().finish();
+        bytes[MAGIC.len()..].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Reader::new(&bytes).err(),
+            Some(format!("saved state has format {}, expected {FORMAT_VERSION}", FORMAT_VERSION + 1)),
+        );
+        assert_eq!(Reader::new(b"SEE").err().as_deref(), Some("not a saved state"));
+        assert_eq!(Reader::new(b"SEE\0\x03").err().as_deref(), Some("saved state is truncated"));
+    }
+
+    #[test]
+    fn truncated_values_fail_to_load(){
+        let mut w = Writer::new();
+        "abc".to_owned().save(&mut w);
+        let bytes = w.finish();
+        let mut r = Reader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(String::load(&mut r), None);
+    }
+}
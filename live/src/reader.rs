@@ -0,0 +1,278 @@
+//! Parses source text into `Expr`s.
+//!
+//! String literals stay atoms with their quotes, so that they are told apart
+//! from symbols and are displayed the way they were written. `'x` reads as
+//! `(quote x)`, `` `x `` as `(quasiquote x)`, `,x` as `(unquote x)` and `,@x`
+//! as `(unquote-splicing x)`. `;` comments out the rest of the line.
+
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
+
+use crate::Expr;
+
+/// Deepest nesting of lists, past it the native stack would overflow when
+/// the forms are laid out or evaluated.
+const MAX_DEPTH: usize = 256;
+
+/// Reads every form in `text`.
+pub fn read_all(text: &str) -> Result<Vec<Expr>, String>{
+    read_with_spans(text).map(|(forms, _)| forms)
+}
+
+/// Reads every form in `text`, along with where each node was.
+pub fn read_with_spans(text: &str) -> Result<(Vec<Expr>, Spans), String>{
+    let mut reader = Reader { text, chars: text.char_indices().peekable(), path: Vec::new(), spans: Vec::new() };
+    let mut forms = Vec::new();
+    while reader.skip_whitespace() {
+        reader.path.push(forms.len());
+        forms.push(reader.form()?);
+        reader.path.pop();
+    }
+    Ok((forms, Spans(reader.spans)))
+}
+
+/// Byte ranges of the nodes read by `read_with_spans`, by their path in a
+/// list of the forms.
+pub struct Spans(Vec<(Vec<usize>, Range<usize>)>);
+
+impl Spans {
+    pub fn span(&self, path: &[usize]) -> Option<Range<usize>>{
+        self.0.iter().find(|(p, _)| p == path).map(|(_, span)| span.clone())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[usize], &Range<usize>)>{
+        self.0.iter().map(|(path, span)| (path.as_slice(), span))
+    }
+
+    /// The path of the innermost node around the byte offset `at`.
+    pub fn path_at(&self, at: usize) -> Option<&[usize]>{
+        self.0
+            .iter()
+            .filter(|(_, span)| span.contains(&at))
+            .max_by_key(|(path, _)| path.len())
+            .map(|(path, _)| path.as_slice())
+    }
+}
+
+/// The byte offset of `line:column` in `text`, both starting at 1 and columns
+/// counted in characters.
+pub fn offset(text: &str, line: usize, column: usize) -> Option<usize>{
+    let line_text = text.split('\n').nth(line.checked_sub(1)?)?;
+    let start: usize = text.split_inclusive('\n').take(line - 1).map(str::len).sum();
+    let within = match line_text.char_indices().nth(column.checked_sub(1)?) {
+        Some((i, _)) => i,
+        // just past the end of the line
+        None if column - 1 == line_text.chars().count() => line_text.len(),
+        None => return None,
+    };
+    Some(start + within)
+}
+
+struct Reader<'a>{
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// Path of the node being read.
+    path: Vec<usize>,
+    spans: Vec<(Vec<usize>, Range<usize>)>,
+}
+
+impl<'a> Reader<'a> {
+    /// Byte offset of the next character.
+    fn offset(&mut self) -> usize{
+        self.chars.peek().map_or(self.text.len(), |&(i, _)| i)
+    }
+
+    /// `line:column` of the byte offset `at`, both starting at 1.
+    fn position(&self, at: usize) -> String{
+        let before = &self.text[..at];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        format!("{line}:{column}")
+    }
+
+    /// Skips whitespace and comments, `false` at the end of the text.
+    fn skip_whitespace(&mut self) -> bool{
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c == ';' {
+                while self.chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            } else if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn form(&mut self) -> Result<Expr, String>{
+        let start = self.offset();
+        if self.path.len() > MAX_DEPTH {
+            return Err(format!("{}: nested deeper than {MAX_DEPTH}", self.position(start)));
+        }
+        let form = self.unspanned_form()?;
+        let end = self.offset();
+        self.spans.push((self.path.clone(), start..end));
+        Ok(form)
+    }
+
+    fn unspanned_form(&mut self) -> Result<Expr, String>{
+        let Some(&(start, c)) = self.chars.peek() else {
+            return Err(format!("{}: unexpected end of input", self.position(self.text.len())));
+        };
+        match c {
+            '(' => {
+                self.chars.next();
+                let mut children = Vec::new();
+                loop {
+                    if !self.skip_whitespace() {
+                        return Err(format!("{}: unclosed `(`", self.position(start)));
+                    }
+                    if self.chars.next_if(|&(_, c)| c == ')').is_some() {
+                        return Ok(Expr::List(children));
+                    }
+                    self.path.push(children.len());
+                    let child = self.form();
+                    self.path.pop();
+                    children.push(child?);
+                }
+            },
+            ')' => Err(format!("{}: unexpected `)`", self.position(start))),
+            '\'' | '`' | ',' => {
+                self.chars.next();
+                let quote = match c {
+                    '\'' => "quote",
+                    '`' => "quasiquote",
+                    _ if self.chars.next_if(|&(_, c)| c == '@').is_some() => "unquote-splicing",
+                    _ => "unquote",
+                };
+                let end = self.offset();
+                self.spans.push(([self.path.as_slice(), &[0]].concat(), start..end));
+                if !self.skip_whitespace() {
+                    return Err(format!("{}: nothing to quote", self.position(start)));
+                }
+                self.path.push(1);
+                let quoted = self.form();
+                self.path.pop();
+                Ok(Expr::List(vec![Expr::Atom(quote.to_owned()), quoted?]))
+            },
+            '"' => self.string(start),
+            _ => {
+                let mut end = self.text.len();
+                while let Some(&(i, c)) = self.chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';' | '`' | ',') {
+                        end = i;
+                        break;
+                    }
+                    self.chars.next();
+                }
+                Ok(Expr::Atom(self.text[start..end].to_owned()))
+            },
+        }
+    }
+
+    /// A string literal, kept as written including its quotes.
+    fn string(&mut self, start: usize) -> Result<Expr, String>{
+        self.chars.next();
+        let mut escaped = false;
+        for (i, c) in self.chars.by_ref() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Ok(Expr::Atom(self.text[start..=i].to_owned())),
+                _ => {},
+            }
+        }
+        Err(format!("{}: unclosed string", self.position(start)))
+    }
+}
+
+/// The contents of a string literal read by `read_all`, `None` if `atom` is not
+/// one.
+pub fn string_literal(atom: &str) -> Option<String>{
+    let inner = atom.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Vec<String>{
+        read_all(text).unwrap().iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn reads_lists_atoms_and_strings(){
+        assert_eq!(read("(a (b \"c d\") ()) e ; f"), ["(a (b \"c d\") ())", "e"]);
+        assert_eq!(read("a\"b\"c"), ["a", "\"b\"", "c"]);
+    }
+
+    #[test]
+    fn quotes_read_as_lists(){
+        assert_eq!(read("'a `(b ,c ,@d)"), ["(quote a)", "(quasiquote (b (unquote c) (unquote-splicing d)))"]);
+    }
+
+    #[test]
+    fn errors_say_where(){
+        assert_eq!(read_all("(a\n  (b)").err().as_deref(), Some("1:1: unclosed `(`"));
+        assert_eq!(read_all("a )").err().as_deref(), Some("1:3: unexpected `)`"));
+        assert_eq!(read_all("(\"ab").err().as_deref(), Some("1:2: unclosed string"));
+        assert_eq!(read_all("'").err().as_deref(), Some("1:1: nothing to quote"));
+    }
+
+    #[test]
+    fn limits_nesting(){
+        let nested = |depth: usize| "(".repeat(depth) + &")".repeat(depth);
+        assert!(read_all(&nested(MAX_DEPTH)).is_ok());
+        let error = format!("1:{}: nested deeper than {MAX_DEPTH}", MAX_DEPTH + 1);
+        assert_eq!(read_all(&nested(MAX_DEPTH + 1)).err(), Some(error.clone()));
+        assert_eq!(read_all(&"(".repeat(200_000)).err(), Some(error));
+    }
+
+    #[test]
+    fn spans_cover_each_node(){
+        let text = "(a 'b)\n  (c)";
+        let (_, spans) = read_with_spans(text).unwrap();
+        let span = |path: &[usize]| spans.span(path).map(|span| &text[span]);
+        assert_eq!(span(&[0]), Some("(a 'b)"));
+        assert_eq!(span(&[0, 1]), Some("'b"));
+        assert_eq!(span(&[0, 1, 0]), Some("'"));
+        assert_eq!(span(&[0, 1, 1]), Some("b"));
+        assert_eq!(span(&[1, 0]), Some("c"));
+        assert_eq!(spans.path_at(4), Some(&[0, 1, 1][..]));
+        assert_eq!(spans.path_at(3), Some(&[0, 1, 0][..]));
+        assert_eq!(spans.path_at(7), None);
+    }
+
+    #[test]
+    fn offsets_count_characters(){
+        let text = "éa\nbc";
+        assert_eq!(offset(text, 1, 1), Some(0));
+        assert_eq!(offset(text, 1, 2), Some(2));
+        assert_eq!(offset(text, 1, 3), Some(3));
+        assert_eq!(offset(text, 2, 2), Some(5));
+        assert_eq!(offset(text, 2, 4), None);
+        assert_eq!(offset(text, 3, 1), None);
+        assert_eq!(offset(text, 0, 1), None);
+    }
+
+    #[test]
+    fn string_literals_unescape(){
+        assert_eq!(string_literal(r#""a\"b\nc\\""#).as_deref(), Some("a\"b\nc\\"));
+        assert_eq!(string_literal("abc"), None);
+    }
+}
@@ -0,0 +1,342 @@
+//! Talks to a Lisp running in another process over a local socket.
+//!
+//! The protocol is line based. The client sends every form on a line of its
+//! own. The server answers each line, in order, with the lines printed while
+//! evaluating it and then the value or an error:
+//!
+//! ```text
+//! -> (print (+ 1 2))
+//! <- out 3
+//! <- ok 3
+//! -> (car 1)
+//! <- err `car` takes a list
+//! ```
+//!
+//! Newlines are sent as `\n` and backslashes as `\\`, the way they are
+//! written in string literals.
+//! Lines of the server without one of the prefixes count as printed.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::Expr;
+use crate::eval::Interpreter;
+use crate::reader;
+use crate::socket::{LineStream, Stream};
+
+pub enum Address{
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Address {
+    /// Parses `tcp:<host>:<port>` or `unix:<path>`, a bare `<host>:<port>` is
+    /// TCP.
+    pub fn parse(text: &str) -> Result<Self, String>{
+        if let Some(path) = text.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Self::Unix(path.into()));
+            #[cfg(not(unix))]
+            return Err(format!("unix sockets are not supported here, can't use {path}"));
+        }
+        let host = text.strip_prefix("tcp:").unwrap_or(text);
+        if !host.contains(':') {
+            return Err(format!("expected `tcp:<host>:<port>` or `unix:<path>`, got `{text}`"));
+        }
+        Ok(Self::Tcp(host.to_owned()))
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(host) => write!(f, "tcp:{host}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The answer to a form sent with `Remote::send`.
+pub struct Reply{
+    pub form: Expr,
+    pub printed: Vec<String>,
+    pub result: Result<Expr, String>,
+}
+
+/// Longest a connection attempt may hold up a frame.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn escape(line: &str) -> String{
+    line.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Undoes `escape`, a backslash before anything else is kept.
+fn unescape(line: &str) -> String{
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            },
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            },
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+/// Tries the addresses `host` resolves to in turn, each for at most
+/// `CONNECT_TIMEOUT`.
+fn connect_tcp(host: &str) -> io::Result<TcpStream>{
+    let mut failed = io::Error::new(ErrorKind::NotFound, "the host has no address");
+    for address in host.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => failed = e,
+        }
+    }
+    Err(failed)
+}
+
+struct Connection{
+    stream: LineStream,
+    /// Forms sent and not answered yet, oldest first.
+    pending: VecDeque<Expr>,
+    /// Printed while evaluating the oldest pending form.
+    printed: Vec<String>,
+}
+
+impl Connection {
+    fn open(address: &Address) -> io::Result<Self>{
+        let stream = match address {
+            Address::Tcp(host) => Stream::Tcp(connect_tcp(host)?),
+            // a local socket accepts or refuses at once
+            #[cfg(unix)]
+            Address::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+        };
+        Ok(Self { stream: LineStream::new(stream)?, pending: VecDeque::new(), printed: Vec::new() })
+    }
+
+    fn send(&mut self, form: &Expr) -> io::Result<()>{
+        self.stream.send(&escape(&String::from(form)))?;
+        self.pending.push_back(form.clone());
+        Ok(())
+    }
+
+    /// Collects the replies received so far, `Err` once the connection is
+    /// closed.
+    fn poll(&mut self, replies: &mut Vec<Reply>) -> Result<(), String>{
+        let mut lines = Vec::new();
+        let status = self.stream.poll(&mut lines);
+        for line in lines {
+            let result = if let Some(value) = line.strip_prefix("ok ") {
+                let value = unescape(value);
+                // values that don't read, like `#<procedure>`, are kept as text
+                Ok(match reader::read_all(&value).as_deref() {
+                    Ok([value]) => value.clone(),
+                    _ => Expr::Atom(value),
+                })
+            } else if let Some(message) = line.strip_prefix("err ") {
+                Err(unescape(message))
+            } else {
+                let text = unescape(line.strip_prefix("out ").unwrap_or(&line));
+                self.printed.extend(text.split('\n').map(str::to_owned));
+                continue;
+            };
+            let Some(form) = self.pending.pop_front() else {
+                return Err(format!("unexpected reply `{line}`"));
+            };
+            let printed = std::mem::take(&mut self.printed);
+            replies.push(Reply { form, printed, result });
+        }
+        status.map_err(|e| format!("connection lost: {e}"))
+    }
+}
+
+/// The external Lisp at an address from the config, connected to on first use.
+pub struct Remote{
+    address: Option<String>,
+    connection: Option<Connection>,
+}
+
+impl Remote {
+    pub fn new(address: Option<String>) -> Self{
+        Self { address, connection: None }
+    }
+
+    /// Sends `form` to be evaluated, connecting first if not connected.
+    pub fn send(&mut self, form: &Expr) -> Result<(), String>{
+        let Some(address) = &self.address else {
+            return Err("no `remote` set in the config".to_owned());
+        };
+        let address = Address::parse(address)?;
+        if self.connection.is_none() {
+            let connection = Connection::open(&address)
+                .map_err(|e| format!("could not connect to {address}: {e}"))?;
+            self.connection = Some(connection);
+        }
+        let connection = self.connection.as_mut().unwrap();
+        connection.send(form).map_err(|e| {
+            self.connection = None;
+            format!("could not send to {address}: {e}")
+        })
+    }
+
+    /// The replies received since the last call.
+    ///
+    /// When the connection is lost, the forms still waiting for a reply get
+    /// the error and the next `send` connects again.
+    pub fn poll(&mut self) -> Vec<Reply>{
+        let mut replies = Vec::new();
+        let Some(connection) = &mut self.connection else {
+            return replies;
+        };
+        if let Err(e) = connection.poll(&mut replies) {
+            let printed = std::mem::take(&mut connection.printed);
+            for form in connection.pending.drain(..) {
+                replies.push(Reply { form, printed: printed.clone(), result: Err(e.clone()) });
+            }
+            self.connection = None;
+        }
+        replies
+    }
+}
+
+/// Serves the protocol with this crate's interpreter, a stand-in for a real
+/// Lisp.
+///
+/// Every connection gets an interpreter of its own. Blocks for as long as
+/// the listener works.
+pub fn serve_stub(address: &Address) -> Result<(), String>{
+    let failed = |e: io::Error| format!("could not listen on {address}: {e}");
+    match address {
+        Address::Tcp(host) => {
+            for stream in TcpListener::bind(host).map_err(failed)?.incoming() {
+                let stream = stream.map_err(failed)?;
+                let reader = stream.try_clone().map_err(failed)?;
+                std::thread::spawn(move || serve(reader, stream));
+            }
+        },
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            // left behind by an earlier server, anything else makes `bind` fail
+            if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                _ = std::fs::remove_file(path);
+            }
+            for stream in UnixListener::bind(path).map_err(failed)?.incoming() {
+                let stream = stream.map_err(failed)?;
+                let reader = stream.try_clone().map_err(failed)?;
+                std::thread::spawn(move || serve(reader, stream));
+            }
+        },
+    }
+    Ok(())
+}
+
+fn serve(reader: impl Read, mut writer: impl Write){
+    let mut interpreter = Interpreter::new();
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else { break };
+        let result = reader::read_all(&unescape(&line)).and_then(|forms| {
+            forms.iter().try_fold(None, |_, form| interpreter.eval_top(form).map(Some))
+        });
+        let mut reply: String = interpreter.output.drain(..).map(|out| format!("out {}\n", escape(&out))).collect();
+        reply += &match result {
+            Ok(Some(value)) => format!("ok {}\n", escape(&value.to_string())),
+            Ok(None) => "ok nil\n".to_owned(),
+            Err(e) => format!("err {}\n", escape(&e)),
+        };
+        if writer.write_all(reply.as_bytes()).and_then(|_| writer.flush()).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Expr{
+        reader::read_all(text).unwrap().remove(0)
+    }
+
+    /// A `Remote` connected to the returned end of a socket pair.
+    fn connected() -> (Remote, UnixStream){
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let stream = LineStream::new(Stream::Unix(ours)).unwrap();
+        let connection = Connection { stream, pending: VecDeque::new(), printed: Vec::new() };
+        (Remote { address: Some("unix:pair".to_owned()), connection: Some(connection) }, theirs)
+    }
+
+    #[test]
+    fn lines_round_trip_escaped(){
+        let (mut remote, theirs) = connected();
+        let sent = Expr::Atom("a\\b\nc".to_owned());
+        remote.send(&sent).unwrap();
+        remote.send(&read("(car 1)")).unwrap();
+        let mut received = BufReader::new(&theirs).lines();
+        assert_eq!(received.next().unwrap().unwrap(), r"a\\b\nc");
+        assert_eq!(received.next().unwrap().unwrap(), "(car 1)");
+
+        (&theirs).write_all(b"out one\\ntwo\nstray\nout c\\\\d\nok (1 2)\nerr `car` takes\\na list\n").unwrap();
+        let replies = remote.poll();
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0].form == sent);
+        assert_eq!(replies[0].printed, ["one", "two", "stray", "c\\d"]);
+        assert!(replies[0].result.as_ref().ok() == Some(&read("(1 2)")));
+        assert!(replies[1].printed.is_empty());
+        assert_eq!(replies[1].result.as_ref().err().map(String::as_str), Some("`car` takes\na list"));
+    }
+
+    #[test]
+    fn a_dropped_connection_fails_the_pending_forms(){
+        let (mut remote, theirs) = connected();
+        remote.send(&read("(f)")).unwrap();
+        remote.send(&read("(g)")).unwrap();
+        (&theirs).write_all(b"ok 1\nout half\n").unwrap();
+        drop(theirs);
+        let replies = remote.poll();
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0].result.as_ref().ok() == Some(&read("1")));
+        assert_eq!(replies[1].printed, ["half"]);
+        assert!(replies[1].result.as_ref().is_err_and(|e| e.starts_with("connection lost")));
+        assert!(remote.connection.is_none());
+    }
+
+    #[test]
+    fn the_stub_answers_in_order(){
+        let (mut remote, theirs) = connected();
+        let reader = theirs.try_clone().unwrap();
+        let server = std::thread::spawn(move || serve(reader, theirs));
+        remote.send(&read("(progn (print 'hi) (+ 1 2))")).unwrap();
+        remote.send(&read("(car 1)")).unwrap();
+        let mut replies = Vec::new();
+        for _ in 0..10_000 {
+            replies.extend(remote.poll());
+            if replies.len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].printed, ["hi"]);
+        assert!(replies[0].result.as_ref().ok() == Some(&read("3")));
+        assert!(replies[1].result.is_err());
+        drop(remote);
+        server.join().unwrap();
+    }
+}
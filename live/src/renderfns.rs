@@ -1,8 +1,10 @@
 use std::marker::PhantomData;
+use std::rc::Rc;
 
 use padding::*;
 
 use crate::{Expr, Tree, Ui, needed_space::*, iterer::*};
+use crate::eval::Run;
 
 use crate::graphics::*;
 
@@ -39,7 +41,7 @@ impl <T, Data> LayoutFn<Data> for T
 where T: Fn(Data, &Ui) -> Tree<NeededSpace>{}
 
 impl Expr {
-    fn car(&self) -> Option<&Expr>{
+    pub fn car(&self) -> Option<&Expr>{
         match self {
             Expr::Atom(_) => None,
             Expr::List(lst) => lst.get(0),
@@ -68,9 +70,10 @@ impl Expr {
         }
     }
 
-    pub fn render_fn(&self) -> PointerRenderFn<&Self>{
+    pub fn render_fn(&self, ui: &Ui) -> PointerRenderFn<&Self>{
         match self {
-            Expr::Atom(_) => SYMBOL,
+            Expr::Atom(_) => ATOM,
+            Expr::List(lst) if !lst.is_empty() && ui.folded.contains(&(self as *const _)) => FOLDED,
             Expr::List(_) => {
                 //match (||{
                 //    let sym = self.car().and_then(Self::sym)?.as_str();
@@ -127,6 +130,7 @@ macro_rules! pointerify {
 }
 
 const DBG_BOXES_ENABLED: bool = false;
+const HIGHLIGHT_COLOR: Color = Color{ r: 60, g: 90, b: 150, a: 160 };
 
 fn dbg_rect(ui: &mut Ui, rect: Rect){
     if DBG_BOXES_ENABLED{
@@ -142,12 +146,32 @@ fn dbg_point(ui: &mut Ui, pos: Pos2){
 }
 
 impl Expr {
+    /// Layout of this node, reusing the retained one if the node is clean.
     pub fn needed_space(&self, ui: &Ui) -> Tree<NeededSpace>{
-        let rfn = self.render_fn();
-        (rfn.layout)(self, ui)
+        if let Some(layout) = ui.layout_cache.and_then(|cache| cache.get(self)) {
+            return Tree::clone(&layout);
+        }
+        let rfn = self.render_fn(ui);
+        let layout = (rfn.layout)(self, ui);
+        if let Some(cache) = ui.layout_cache {
+            cache.insert(self, Rc::new(layout.clone()));
+        }
+        layout
     }
     pub fn draw(&self, ui: &mut Ui, rect: Rect, space: &Tree<NeededSpace>) -> Response{
-        let rfn = self.render_fn();
+        if !rect.intersects(ui.visible) {
+            return;
+        }
+        if ui.mouse.is_some_and(|m| rect.contains(m)) {
+            ui.hovered = Some(self);
+        }
+        if ui.highlight == Some(self as *const _) {
+            ui.highlighted = Some(rect);
+        }
+        if ui.highlight == Some(self as *const _) || ui.linked.is_some_and(|linked| linked.contains(&(self as *const _))) {
+            ui.draw_rect(rect, HIGHLIGHT_COLOR);
+        }
+        let rfn = self.render_fn(ui);
         (rfn.draw)(self, ui, rect, space)
     }
 
@@ -273,6 +297,9 @@ D: DrawFn<&'a Data>,
         draw: move|data, ui, rect, space|{
             let mut cursor_top_left = rect.min;
             for (child, space) in data.iter().zip(space.children.iter()){
+                if cursor_top_left.y > ui.visible.bottom() {
+                    break;
+                }
                 let size = space.value.size();
                 let rect = Rect::from_min_size(cursor_top_left, size);
                 (f.draw)(child, ui, rect, space);
@@ -349,7 +376,7 @@ B: Into<String> + Copy,
                 Some(a) => (f.layout)(a, ui),
                 None => {
                     let text: String = b.into();
-                    let size = ui.measure(ui.font, &text, ui.text_scale);
+                    let size = ui.text_size(&text);
 
                     Tree::leaf(NeededSpace::above(size))
                 },
@@ -362,7 +389,7 @@ B: Into<String> + Copy,
                 None => {
                     let text: String = b.into();
 
-                    ui.g.draw_text_ex(ui.font, ui.sdf_shader, &text, rect.min, ui.text_scale, colors::RED);
+                    ui.paint_text(&text, rect.min, colors::RED);
                 },
             }
         },
@@ -370,6 +397,37 @@ B: Into<String> + Copy,
     }
 }
 
+/// Draws `f` at the top left of the rect it is given, cutting off whatever
+/// does not fit.
+///
+/// Lays out like `f`, the parent decides how much of it is shown by the rect
+/// it hands out.
+fn clip<Data, L, D>(f: RenderFn<Data, L, D>)
+-> RenderFn<Data, impl LayoutFn<Data>, impl DrawFn<Data>>
+where
+L: LayoutFn<Data>,
+D: DrawFn<Data>,
+{
+    RenderFn {
+        layout: move|data, ui|{
+            let tree = (f.layout)(data, ui);
+            Tree::new(tree.value, vec![tree])
+        },
+        draw: move|data, ui, rect, space|{
+            let space = space.children.get(0).expect("clip was used wrong");
+            let visible = ui.visible;
+            ui.visible = visible.intersect(rect);
+            if ui.visible.is_positive() {
+                ui.push_clip(rect);
+                (f.draw)(data, ui, Rect::from_min_size(rect.min, space.value.size()), space);
+                ui.pop_clip();
+            }
+            ui.visible = visible;
+        },
+        ph: PhantomData,
+    }
+}
+
 const PADDING: f32 = 4.0;
 
 pointerify!(ROW_CP = map(Expr::cdr_unwrap, row_centered(pad(GENERAL, PADDING))));
@@ -379,6 +437,54 @@ pointerify!(ROW_CENTERED = map(Expr::cdr_unwrap, row_centered(GENERAL)));
 pointerify!(COL = map(Expr::cdr_unwrap, col(GENERAL)));
 pointerify!(COL_CENTERED = map(Expr::cdr_unwrap, col_centered(GENERAL)));
 
+const TOP_LEVEL_SPACING: f32 = 10.0;
+// the root of a document, its top-level forms below each other
+pointerify!(TOP_LEVEL = map(Expr::forms, col(pad(with_result(GENERAL), TOP_LEVEL_SPACING))));
+
+const MACRO_GAP: f32 = 8.0;
+// a list of rows, each laid out from left to right, as in a `MacroView`
+pointerify!(ROWS = map(Expr::forms, col(pad(map(Expr::forms, row(pad(GENERAL, MACRO_GAP))), TOP_LEVEL_SPACING))));
+
+const RESULT_GAP: f32 = 20.0;
+const RESULT_COLOR: Color = Color{ r: 255, g: 255, b: 255, a: 100 };
+const RESULT_ERROR_COLOR: Color = Color{ r: 230, g: 41, b: 55, a: 150 };
+
+/// Text and color of the result of the top-level form `form` in `run`.
+pub fn result_annotation(run: &Run, form: &Expr) -> Option<(String, Color)>{
+    Some(match run.result(form)? {
+        Ok(value) => (format!("=> {value}"), RESULT_COLOR),
+        Err(e) => (format!("error: {e}"), RESULT_ERROR_COLOR),
+    })
+}
+
+/// `f`, followed by the result of evaluating the form to its right.
+fn with_result<'a, L, D>(f: RenderFn<&'a Expr, L, D>)
+-> RenderFn<&'a Expr, impl LayoutFn<&'a Expr>, impl DrawFn<&'a Expr>>
+where
+L: LayoutFn<&'a Expr>,
+D: DrawFn<&'a Expr>,
+{
+    RenderFn {
+        layout: move|data, ui|{
+            let tree = (f.layout)(data, ui);
+            let value = match ui.run.and_then(|run| result_annotation(run, data)) {
+                Some((text, _)) => tree.value.add_x(NeededSpace::above(ui.text_size(&text)).expand_x(RESULT_GAP)),
+                None => tree.value,
+            };
+            Tree::new(value, vec![tree])
+        },
+        draw: move|data, ui, rect, space|{
+            let space = space.children.get(0).expect("with_result was used wrong");
+            let form = Rect::from_min_size(rect.min, space.value.size());
+            (f.draw)(data, ui, form, space);
+            if let Some((text, color)) = ui.run.and_then(|run| result_annotation(run, data)) {
+                ui.paint_text(&text, form.right_top() + Vec2::RIGHT * RESULT_GAP, color);
+            }
+        },
+        ph: PhantomData,
+    }
+}
+
 const FRACT_OVERHANG: f32 = 10.0;
 
 /*
@@ -513,7 +619,13 @@ define_render!(
             let rect = rect.shrink(SEXPR_PADDING);
 
             let rounding = 10.0;
-            //let stroke = Stroke::new(2.0, Color32::from_gray(80));
+            let roundness = rounding / rect.size().smaller_comp();
+            let depth = ui.depth;
+            // below the children, the boxes of deeper lists are drawn later
+            if let Some(fill) = ui.palette.fill(depth) {
+                ui.draw_rect_rounded(rect, roundness, 10, fill);
+            }
+            ui.depth += 1;
 
             let (row_size, row_elem_count) = layouts
                 .iter()
@@ -541,14 +653,19 @@ define_render!(
             cursor_top_left.y += row_size.size().y;
             cursor_top_left.x += INDENT;
             for (space, child) in layout_iter{
+                if cursor_top_left.y > ui.visible.bottom() {
+                    break;
+                }
                 let size = space.value.size();
                 let rect = Rect::from_min_size(cursor_top_left, size);
                 pad_draw(child, ui, rect, &space);
                 cursor_top_left.y += size.y;
             }
+            ui.depth = depth;
 
 
-            ui.draw_rect_rounded_lines(rect, rounding / rect.size().smaller_comp(), 10, 0.5, colors::WHITE);
+            let outline = ui.palette.outline(depth);
+            ui.draw_rect_rounded_lines(rect, roundness, 10, 0.5, outline);
             //ui.painter()
             //    .add(Shape::Rect(RectShape::stroke(rect, rounding, stroke)));
 
@@ -558,19 +675,83 @@ define_render!(
 );
 
 
+const FOLDED_COLOR: Color = Color{ r: 255, g: 255, b: 255, a: 120 };
+/// Heads wider than this are cut off, a folded list is meant to be small.
+const FOLDED_HEAD_WIDTH: f32 = 200.0;
+
+/// What stands in for the `hidden` children of a folded list.
+pub fn folded_label(hidden: usize) -> String{
+    format!("… {hidden}")
+}
+/// Every glyph `folded_label` uses.
+pub const FOLDED_GLYPHS: &str = "… 0123456789";
+
+// a folded list, its head followed by how many children are hidden
+define_render!(
+    FOLDED(
+        layout(data, ui){
+            let children = data.lst().unwrap();
+            let head = clip(pad(GENERAL, PADDING)).layout;
+            let head = head(&children[0], ui);
+            let shown = NeededSpace { size_x: head.value.size_x.min(FOLDED_HEAD_WIDTH), ..head.value };
+            let label = ui.text_size(&folded_label(children.len() - 1));
+            let label = NeededSpace::above(label).expand(PADDING);
+            Tree::new(shown.add_x(label).expand(SEXPR_PADDING), vec![head])
+        }
+        draw(data, ui, rect, space){
+            let children = data.lst().unwrap();
+            let head = space.children.get(0).expect("FOLDED was used wrong");
+            let rect = rect.shrink(SEXPR_PADDING);
+            let label = folded_label(children.len() - 1);
+            let label_size = ui.text_size(&label);
+
+            let row_above = head.value.size_y_above.max(label_size.y + PADDING);
+            let head_min = rect.min + Vec2::DOWN * (row_above - head.value.size_y_above);
+            let head_size = vec2(head.value.size_x.min(FOLDED_HEAD_WIDTH), head.value.size().y);
+            let head_rect = Rect::from_min_size(head_min, head_size);
+            ui.depth += 1;
+            (clip(pad(GENERAL, PADDING)).draw)(&children[0], ui, head_rect, head);
+            ui.depth -= 1;
+            let label_min = vec2(head_rect.right() + PADDING, rect.top() + row_above - label_size.y);
+            ui.paint_text(&label, label_min, FOLDED_COLOR);
+
+            let rounding = 10.0;
+            ui.draw_rect_rounded_lines(rect, rounding / rect.size().smaller_comp(), 10, 0.5, FOLDED_COLOR);
+        }
+    )
+);
 
 define_render!(
     SYMBOL(
         layout(data, ui){
-            let size = ui.measure(ui.font, &String::from(data), ui.text_scale);
+            let size = match data {
+                Expr::Atom(text) => ui.text_size(text),
+                _ => ui.text_size(&String::from(data)),
+            };
             Tree::leaf(NeededSpace::above(size))
         }
         draw(data, ui, rect, _space){
             //dbg_rect(ui, rect);
-            let scale = ui.text_scale;
-            let shader = ui.sdf_shader.clone();
-            let font = ui.font;
-            ui.draw_text_ex(font, shader, &String::from(data), rect.min, scale, colors::WHITE);
+            match data {
+                Expr::Atom(text) => ui.paint_text(text, rect.min, colors::WHITE),
+                _ => ui.paint_text(&String::from(data), rect.min, colors::WHITE),
+            }
+        }
+    )
+);
+/// Atoms wider than this, like long strings or the values shown in place of
+/// nodes, are cut off.
+const ATOM_WIDTH: f32 = 600.0;
+
+define_render!(
+    ATOM(
+        layout(data, ui){
+            let text = (clip(SYMBOL).layout)(data, ui);
+            let shown = NeededSpace { size_x: text.value.size_x.min(ATOM_WIDTH), ..text.value };
+            Tree::new(shown, vec![text])
+        }
+        draw(data, ui, rect, space){
+            (clip(SYMBOL).draw)(data, ui, rect, &space.children[0]);
         }
     )
 );
@@ -0,0 +1,150 @@
+//! A prompt evaluating what is typed into it, with the entries so far.
+
+use crate::Expr;
+use crate::document::Document;
+use crate::eval::Interpreter;
+use crate::graphics::*;
+use crate::persist::{self, Persist};
+use crate::reader;
+use crate::remote::Reply;
+
+/// Rows of the history kept, older ones are dropped.
+const MAX_HISTORY: usize = 1000;
+
+pub struct Repl{
+    /// Keeps its definitions from one entry to the next.
+    interpreter: Interpreter,
+    /// Text typed after the prompt.
+    pub input: String,
+    /// Inputs submitted so far, for recalling them.
+    inputs: Vec<String>,
+    /// Index into `inputs` of the recalled one.
+    recalled: Option<usize>,
+    /// A row per line of the entries: the form and its value, and the lines
+    /// it printed.
+    pub history: Document,
+    /// How far the history is scrolled up from its latest entry.
+    pub scroll: f32,
+}
+
+/// The entries and inputs, the definitions are not kept.
+impl Persist for Repl {
+    fn save(&self, w: &mut persist::Writer) {
+        self.input.save(w);
+        self.inputs.save(w);
+        self.history.save(w);
+        self.scroll.save(w);
+    }
+    fn load(r: &mut persist::Reader) -> Option<Self> {
+        Some(Self {
+            input: String::load(r)?,
+            inputs: Vec::load(r)?,
+            history: Document::load(r)?,
+            scroll: f32::load(r)?,
+            ..Self::new()
+        })
+    }
+}
+
+fn atom(text: impl Into<String>) -> Expr{
+    Expr::Atom(text.into())
+}
+
+impl Repl {
+    pub fn new() -> Self{
+        Self {
+            interpreter: Interpreter::new(),
+            input: String::new(),
+            inputs: Vec::new(),
+            recalled: None,
+            history: Document::new(Expr::List(Vec::new())),
+            scroll: 0.0,
+        }
+    }
+
+    /// Takes the typed characters, `Enter` submits the input and `Up`/`Down`
+    /// go through the earlier ones.
+    pub fn handle_input(&mut self, g: &Graphics){
+        while let Some(c) = g.char_pressed() {
+            self.input.push(c);
+        }
+        if g.is_key_pressed(Key::Backspace) || g.is_key_pressed_repeat(Key::Backspace) {
+            self.input.pop();
+        }
+        if g.is_key_pressed(Key::Up) && !self.inputs.is_empty() {
+            let i = self.recalled.map_or(self.inputs.len() - 1, |i| i.saturating_sub(1));
+            self.recall(Some(i));
+        }
+        if g.is_key_pressed(Key::Down) {
+            let i = self.recalled.map(|i| i + 1).filter(|&i| i < self.inputs.len());
+            self.recall(i);
+        }
+        if g.is_key_pressed(Key::Enter) {
+            self.submit();
+        }
+    }
+
+    fn recall(&mut self, i: Option<usize>){
+        self.recalled = i;
+        self.input = i.map(|i| self.inputs[i].clone()).unwrap_or_default();
+    }
+
+    /// Evaluates the input, adding an entry per form to the history.
+    ///
+    /// Input that doesn't read stays in the prompt to be fixed.
+    fn submit(&mut self){
+        if self.input.trim().is_empty() {
+            return;
+        }
+        let forms = match reader::read_all(&self.input) {
+            Ok(forms) => forms,
+            Err(e) => {
+                self.push(vec![atom(self.input.clone()), atom(format!("error: {e}"))]);
+                return;
+            },
+        };
+        for form in forms {
+            let result = self.interpreter.eval_top(&form).map(|value| value.to_expr());
+            let printed = self.interpreter.output.drain(..).collect();
+            self.push_entry(form, printed, result);
+        }
+        self.inputs.push(std::mem::take(&mut self.input));
+        self.recalled = None;
+        self.scroll = 0.0;
+    }
+
+    /// Starts over with a fresh interpreter, keeping the history.
+    pub fn reset(&mut self){
+        self.interpreter = Interpreter::new();
+        if !self.inputs.is_empty() {
+            self.push(vec![atom("the library was reloaded, earlier definitions are gone")]);
+        }
+    }
+
+    /// Adds the answer of an external Lisp to the history.
+    pub fn push_reply(&mut self, reply: Reply){
+        self.push_entry(reply.form, reply.printed, reply.result);
+    }
+
+    fn push_entry(&mut self, form: Expr, printed: Vec<String>, result: Result<Expr, String>){
+        for line in printed {
+            self.push(vec![atom(line)]);
+        }
+        let row = match result {
+            Ok(value) => vec![form, atom("=>"), value],
+            Err(e) => vec![form, atom(format!("error: {e}"))],
+        };
+        self.push(row);
+    }
+
+    /// Adds a row to the history, the other rows keep their layouts.
+    fn push(&mut self, row: Vec<Expr>){
+        if let Expr::Atom(_) = self.history.root() {
+            self.history.set_root(Expr::List(Vec::new()));
+        }
+        self.history.push(&[], Expr::List(row));
+        if self.history.forms().len() > MAX_HISTORY {
+            self.history.remove(&[0]);
+        }
+    }
+}
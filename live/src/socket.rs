@@ -0,0 +1,164 @@
+//! Non-blocking sockets, polled once a frame.
+//!
+//! A thread blocked on a socket would still be running code of this library
+//! after the host unloaded it for a reload.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub enum Stream{
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_nonblocking(&self) -> io::Result<()>{
+        match self {
+            Self::Tcp(stream) => stream.set_nonblocking(true),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_nonblocking(true),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Bytes waiting to be sent past this, the other end is taken to not read.
+const MAX_OUTGOING: usize = 1 << 20;
+/// Bytes of a line received past this, the other end is taken to not send
+/// lines.
+const MAX_INCOMING: usize = 1 << 20;
+
+/// A stream of lines, in both directions.
+pub struct LineStream{
+    stream: Stream,
+    /// Received bytes not ending a line yet.
+    partial: Vec<u8>,
+    /// Bytes the socket didn't take yet.
+    outgoing: Vec<u8>,
+}
+
+impl LineStream {
+    pub fn new(stream: Stream) -> io::Result<Self>{
+        stream.set_nonblocking()?;
+        Ok(Self { stream, partial: Vec::new(), outgoing: Vec::new() })
+    }
+
+    /// Appends the lines received since the last call to `lines` and sends
+    /// on the ones queued by `send`, `Err` once the stream is closed.
+    pub fn poll(&mut self, lines: &mut Vec<String>) -> io::Result<()>{
+        let written = self.write_outgoing();
+        let mut chunk = [0; 4096];
+        let status = loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => break Err(io::Error::new(ErrorKind::UnexpectedEof, "closed by the other end")),
+                Ok(n) => {
+                    self.partial.extend_from_slice(&chunk[..n]);
+                    self.take_lines(lines);
+                    if self.partial.len() >= MAX_INCOMING {
+                        break Err(io::Error::new(ErrorKind::InvalidData, "received a line that doesn't end"));
+                    }
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => break Err(e),
+            }
+        };
+        written.and(status)
+    }
+
+    /// Moves the complete lines received to `lines`.
+    fn take_lines(&mut self, lines: &mut Vec<String>){
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            lines.push(line.trim_end_matches('\r').to_owned());
+        }
+    }
+
+    /// Sends `line`, as much of it as the socket takes now and the rest from
+    /// `poll`.
+    pub fn send(&mut self, line: &str) -> io::Result<()>{
+        if self.outgoing.len() + line.len() >= MAX_OUTGOING {
+            return Err(io::Error::new(ErrorKind::WriteZero, "the other end stopped reading"));
+        }
+        self.outgoing.extend_from_slice(line.as_bytes());
+        self.outgoing.push(b'\n');
+        self.write_outgoing()
+    }
+
+    /// Writes the queued bytes until the socket would block.
+    fn write_outgoing(&mut self) -> io::Result<()>{
+        let mut sent = 0;
+        let status = loop {
+            if sent == self.outgoing.len() {
+                break Ok(());
+            }
+            match self.stream.write(&self.outgoing[sent..]) {
+                Ok(0) => break Err(ErrorKind::WriteZero.into()),
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => break Err(e),
+            }
+        };
+        self.outgoing.drain(..sent);
+        status
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_that_never_end_are_an_error(){
+        let (ours, mut theirs) = UnixStream::pair().unwrap();
+        let mut stream = LineStream::new(Stream::Unix(ours)).unwrap();
+        let writer = std::thread::spawn(move || {
+            _ = theirs.write_all(b"first\n");
+            _ = theirs.write_all(&vec![b'x'; MAX_INCOMING]);
+        });
+        let mut lines = Vec::new();
+        let mut status = Ok(());
+        for _ in 0..10_000 {
+            status = stream.poll(&mut lines);
+            if status.is_err() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(status.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+        assert_eq!(lines, ["first"]);
+        drop(stream);
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! The file shown, read again whenever another editor saves it.
+
+use std::path::{Path, PathBuf};
+
+use crate::Expr;
+use crate::document::Document;
+use crate::reader::{self, Spans};
+use crate::watch::Watcher;
+
+/// A reading of the file.
+struct Version{
+    text: String,
+    spans: Spans,
+}
+
+impl Version {
+    fn read(path: &Path) -> Result<(Self, Vec<Expr>), String>{
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        let (forms, spans) = reader::read_with_spans(&text)
+            .map_err(|e| format!("{}:{e}", path.display()))?;
+        Ok((Self { text, spans }, forms))
+    }
+}
+
+/// The file as it was before it was read again, to find where its nodes went.
+pub struct Before{
+    forms: Vec<Expr>,
+    version: Version,
+}
+
+pub struct Source{
+    pub path: PathBuf,
+    current: Version,
+    /// `Document::revision` the file was last read at.
+    pub revision: u64,
+    watcher: Watcher,
+    /// Why the file didn't read the last time it changed, the document stays
+    /// as it was until it does again.
+    pub error: Option<String>,
+    /// Top-level form at the top of the view and how far into it the view
+    /// starts, as of the last frame.
+    pub anchor: Option<(usize, f32)>,
+}
+
+impl Source {
+    /// Reads the file at `path` into `doc`.
+    pub fn open(path: PathBuf, doc: &mut Document) -> Result<Self, String>{
+        let watcher = Watcher::new(&path);
+        let (current, forms) = Version::read(&path)?;
+        doc.set_root(Expr::List(forms));
+        Ok(Self { path, current, revision: doc.revision(), watcher, error: None, anchor: None })
+    }
+
+    /// Reads the file into `doc` again if it changed on disk.
+    pub fn poll(&mut self, doc: &mut Document) -> Option<Before>{
+        if !self.watcher.poll() {
+            return None;
+        }
+        let (version, forms) = match Version::read(&self.path) {
+            Ok(read) => read,
+            Err(e) => {
+                self.error = Some(e);
+                return None;
+            },
+        };
+        self.error = None;
+        let before = Before {
+            forms: doc.forms().to_vec(),
+            version: std::mem::replace(&mut self.current, version),
+        };
+        doc.set_root(Expr::List(forms));
+        self.revision = doc.revision();
+        Some(before)
+    }
+
+    /// The path of the innermost node at `line:column` of the file.
+    pub fn path_at(&self, line: usize, column: usize) -> Option<Vec<usize>>{
+        let at = reader::offset(&self.current.text, line, column)?;
+        self.current.spans.path_at(at).map(<[usize]>::to_vec)
+    }
+
+    /// Where the node that was at `path` before the file was read again is
+    /// in `doc` now.
+    ///
+    /// An unchanged node is looked for where it was, then anywhere near where
+    /// its text moved to. A changed one is put in the same place of where its
+    /// parent went, and a changed top-level form is matched by its first two
+    /// atoms, like `defun` and the name.
+    pub fn follow(&self, before: &Before, doc: &Document, path: &[usize]) -> Option<Vec<usize>>{
+        let (&first, rest) = path.split_first()?;
+        let old = before.forms.get(first).and_then(|form| get(form, rest))?;
+        if doc.get(path) == Some(old) {
+            return Some(path.to_vec());
+        }
+        let start = before.version.spans.span(path)?.start;
+        let at = moved(&before.version.text, &self.current.text, start);
+        let closest = |matches: &dyn Fn(&[usize]) -> bool| {
+            self.current.spans
+                .iter()
+                .filter(|(p, _)| matches(p))
+                .min_by_key(|(_, span)| span.start.abs_diff(at))
+                .map(|(p, _)| p.to_vec())
+        };
+        if let Some(same) = closest(&|p| doc.get(p) == Some(old)) {
+            return Some(same);
+        }
+        if let Some((&last, parent)) = path.split_last().filter(|(_, parent)| !parent.is_empty()) {
+            let mut moved = self.follow(before, doc, parent)?;
+            moved.push(last);
+            return Some(if doc.get(&moved).is_some() { moved } else { parent.to_vec() });
+        }
+        closest(&|p| p.len() == 1 && key(old).is_some() && doc.get(p).and_then(key) == key(old))
+            .or_else(|| Some(vec![*self.current.spans.path_at(at)?.first()?]))
+    }
+}
+
+/// The first two atoms of a list.
+fn key(form: &Expr) -> Option<(&str, &str)>{
+    match form {
+        Expr::List(items) => match items.as_slice() {
+            [Expr::Atom(a), Expr::Atom(b), ..] => Some((a, b)),
+            _ => None,
+        },
+        Expr::Atom(_) => None,
+    }
+}
+
+fn get<'a>(node: &'a Expr, path: &[usize]) -> Option<&'a Expr>{
+    path.iter().try_fold(node, |node, &i| match node {
+        Expr::List(children) => children.get(i),
+        Expr::Atom(_) => None,
+    })
+}
+
+/// Where the byte offset `at` of `old` is in `new`, taking the text between
+/// their common start and end as the part that was edited.
+fn moved(old: &str, new: &str, at: usize) -> usize{
+    let prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    let suffix = old.bytes().rev().zip(new.bytes().rev()).take_while(|(a, b)| a == b).count();
+    let suffix = suffix.min(old.len().min(new.len()) - prefix);
+    if at < prefix {
+        at
+    } else if at >= old.len() - suffix {
+        new.len() - (old.len() - at)
+    } else {
+        // inside the edit, its start is the closest there is
+        prefix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `old` read again as `new`, as `Source::poll` would.
+    fn reread(old: &str, new: &str) -> (Source, Before, Document){
+        let read = |text: &str| {
+            let (forms, spans) = reader::read_with_spans(text).unwrap();
+            (Version { text: text.to_owned(), spans }, forms)
+        };
+        let (old, old_forms) = read(old);
+        let (current, forms) = read(new);
+        let doc = Document::new(Expr::List(forms));
+        let source = Source {
+            path: PathBuf::from("test.lisp"),
+            current,
+            revision: doc.revision(),
+            watcher: Watcher::new("test.lisp"),
+            error: None,
+            anchor: None,
+        };
+        (source, Before { forms: old_forms, version: old }, doc)
+    }
+
+    #[test]
+    fn offsets_move_with_the_edit(){
+        assert_eq!(moved("a b c", "xx a b c", 2), 5);
+        assert_eq!(moved("a b c", "a bb c", 4), 5);
+        assert_eq!(moved("a b c", "a b c x", 4), 4);
+        // inside the edit
+        assert_eq!(moved("a xyz c", "a q c", 3), 2);
+    }
+
+    #[test]
+    fn nodes_follow_edits(){
+        let old = "(defun a (x) (+ x 1))\n(defun b (y) (* y 2))\n(b 3)\n";
+        let new = "(defun new () 0)\n(defun a (x) (+ x 10))\n(defun b (y) (* y 2))\n(b 3)\n";
+        let (source, before, doc) = reread(old, new);
+        let follow = |path: &[usize]| source.follow(&before, &doc, path);
+        // unchanged, found where their text moved
+        assert_eq!(follow(&[1]), Some(vec![2]));
+        assert_eq!(follow(&[1, 3, 1]), Some(vec![2, 3, 1]));
+        assert_eq!(follow(&[2, 1]), Some(vec![3, 1]));
+        // changed, in the same place of where the parent went
+        assert_eq!(follow(&[0, 3]), Some(vec![1, 3]));
+        assert_eq!(follow(&[0, 3, 2]), Some(vec![1, 3, 2]));
+        // a changed top-level form, by `defun a`
+        assert_eq!(follow(&[0]), Some(vec![1]));
+    }
+
+    #[test]
+    fn unchanged_nodes_stay(){
+        let text = "(a b)\n(c d)\n";
+        let (source, before, doc) = reread(text, text);
+        assert_eq!(source.follow(&before, &doc, &[1, 1]), Some(vec![1, 1]));
+        assert_eq!(source.follow(&before, &doc, &[2]), None);
+    }
+
+    #[test]
+    fn removed_children_fall_back_to_the_parent(){
+        let (source, before, doc) = reread("(f a b c)", "(f a)");
+        assert_eq!(source.follow(&before, &doc, &[0, 3]), Some(vec![0]));
+    }
+}
@@ -0,0 +1,167 @@
+//! Replays an evaluation of the document one step at a time.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Expr;
+use crate::document::Document;
+use crate::eval::{Interpreter, Step, Trace};
+
+pub struct Stepper{
+    trace: Trace,
+    /// Copy of the document the trace was made of, boxed to keep the
+    /// addresses in the trace.
+    source: Box<Expr>,
+    /// `Document::revision` the trace was made at.
+    pub revision: u64,
+    current: usize,
+    /// `source` as of the current step, with the nodes evaluated so far
+    /// replaced by their values.
+    pub view: Document,
+    /// Paths of the nodes replaced in `view`, with the step whose value
+    /// replaces them.
+    shown: BTreeMap<Vec<usize>, usize>,
+}
+
+impl Stepper {
+    /// Evaluates the forms of `doc` with a fresh interpreter, recording every
+    /// step.
+    pub fn new(doc: &Document) -> Self{
+        let source = Box::new(doc.root().clone());
+        let mut interpreter = Interpreter::new();
+        interpreter.trace = Some(Trace::new(&source));
+        for form in source.forms() {
+            // a failing form just ends its steps early
+            _ = interpreter.eval_top(form);
+        }
+        let trace = interpreter.trace.take().unwrap();
+        let view = Document::new(doc.root().clone());
+        let mut stepper = Self { trace, source, revision: doc.revision(), current: 0, view, shown: BTreeMap::new() };
+        stepper.seek(0);
+        stepper
+    }
+
+    pub fn len(&self) -> usize{
+        self.trace.steps.len()
+    }
+
+    pub fn current(&self) -> usize{
+        self.current
+    }
+
+    /// `true` if the evaluation took more steps than were recorded.
+    pub fn truncated(&self) -> bool{
+        self.trace.truncated
+    }
+
+    /// The node of `view` the current step evaluates.
+    pub fn highlight(&self) -> Option<&Expr>{
+        let step = self.trace.steps.get(self.current)?;
+        self.view.get(&step.path)
+    }
+
+    /// Shows the document as of `step`, clamped to the recorded ones.
+    ///
+    /// Only the nodes whose value changed since the last step shown are
+    /// replaced, the rest of `view` keeps its layout.
+    pub fn seek(&mut self, step: usize){
+        self.current = step.min(self.len().saturating_sub(1));
+        let mut shown: BTreeMap<&[usize], usize> = BTreeMap::new();
+        for (i, step) in self.trace.steps.iter().enumerate().take(self.current + 1) {
+            let path = step.path.as_slice();
+            match &step.value {
+                Some(_) => {
+                    shown.insert(path, i);
+                },
+                None => {
+                    // evaluated again, forget the values of the last time
+                    let stale: Vec<_> = shown
+                        .range(path..)
+                        .map(|(k, _)| *k)
+                        .take_while(|k| k.starts_with(path))
+                        .collect();
+                    stale.iter().for_each(|k| { shown.remove(k); });
+                },
+            }
+        }
+        let mut changed: BTreeSet<&[usize]> = shown
+            .iter()
+            .filter(|&(path, i)| self.shown.get(*path) != Some(i))
+            .map(|(path, _)| *path)
+            .collect();
+        changed.extend(self.shown.keys().map(Vec::as_slice).filter(|path| !shown.contains_key(path)));
+        // sorted, the paths below a replaced node follow it
+        let mut replaced: Option<&[usize]> = None;
+        for path in changed {
+            if replaced.is_some_and(|r| path.starts_with(r)) {
+                continue;
+            }
+            // hidden by the value of a node above it
+            if (0..path.len()).any(|len| shown.contains_key(&path[..len])) {
+                continue;
+            }
+            let Some(node) = self.source.at(path) else {
+                continue;
+            };
+            self.view.replace(path, substitute(node, &mut path.to_vec(), &shown, &self.trace.steps));
+            replaced = Some(path);
+        }
+        self.shown = shown.into_iter().map(|(path, i)| (path.to_vec(), i)).collect();
+    }
+}
+
+/// A copy of `node`, at `path`, with the nodes that have a value in `shown`
+/// replaced by the value of their step.
+fn substitute(node: &Expr, path: &mut Vec<usize>, shown: &BTreeMap<&[usize], usize>, steps: &[Step]) -> Expr{
+    if let Some(value) = shown.get(path.as_slice()).and_then(|&i| steps[i].value.as_ref()) {
+        return value.to_expr();
+    }
+    match node {
+        Expr::Atom(atom) => Expr::Atom(atom.clone()),
+        Expr::List(children) => Expr::List(children.iter().enumerate().map(|(i, child)| {
+            path.push(i);
+            let child = substitute(child, path, shown, steps);
+            path.pop();
+            child
+        }).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::Tree;
+    use crate::needed_space::NeededSpace;
+    use crate::reader;
+
+    fn stepper(text: &str) -> Stepper{
+        Stepper::new(&Document::new(Expr::List(reader::read_all(text).unwrap())))
+    }
+
+    /// The view of the current step made from scratch.
+    fn expected(stepper: &Stepper) -> String{
+        let shown = stepper.shown.iter().map(|(path, &i)| (path.as_slice(), i)).collect();
+        String::from(&substitute(&stepper.source, &mut Vec::new(), &shown, &stepper.trace.steps))
+    }
+
+    #[test]
+    fn seeking_anywhere_shows_the_same_as_from_the_start(){
+        let mut stepper = stepper("(define x (+ 1 2)) (print (* x (+ x 1))) (if (> x 2) 'big 'small)");
+        let len = stepper.len();
+        for step in (0..len).chain((0..len).rev()).chain([len / 2, 1, len - 1, 0]) {
+            stepper.seek(step);
+            assert_eq!(String::from(stepper.view.root()), expected(&stepper), "step {step}");
+        }
+    }
+
+    #[test]
+    fn forms_not_stepped_into_keep_their_layout(){
+        let mut stepper = stepper("(+ 1 2) (list 3 4)");
+        let second = stepper.view.get(&[1]).unwrap();
+        stepper.view.layout.insert(second, Rc::new(Tree::leaf(NeededSpace::ZERO)));
+        stepper.seek(2);
+        assert_eq!(String::from(stepper.view.root()), "(3 (list 3 4))");
+        assert!(stepper.view.layout.get(stepper.view.get(&[1]).unwrap()).is_some());
+    }
+}
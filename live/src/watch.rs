@@ -0,0 +1,82 @@
+//! Polls files for changes, shared with the host.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a file has to stay unchanged before a change is reported.
+///
+/// The linker writes the library in several steps, loading it in between
+/// would pick up a truncated file. Editors saving sources behave similarly.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Stamp{
+    modified: Option<SystemTime>,
+    len: u64,
+    files: usize,
+}
+
+impl Stamp {
+    /// Stamps a file, or every file below a directory.
+    fn of(path: &Path) -> Option<Self>{
+        let meta = std::fs::metadata(path).ok()?;
+        if !meta.is_dir() {
+            return Some(Self {
+                modified: meta.modified().ok(),
+                len: meta.len(),
+                files: 1,
+            });
+        }
+        let mut stamp = Self { modified: None, len: 0, files: 0 };
+        for entry in std::fs::read_dir(path).ok()?.flatten() {
+            if let Some(child) = Self::of(&entry.path()) {
+                stamp.modified = stamp.modified.max(child.modified);
+                stamp.len += child.len;
+                stamp.files += child.files;
+            }
+        }
+        Some(stamp)
+    }
+}
+
+/// Polls a file, or a directory tree, for changes.
+pub struct Watcher{
+    path: PathBuf,
+    current: Option<Stamp>,
+    pending: Option<(Stamp, Instant)>,
+}
+
+impl Watcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self{
+        let path = path.into();
+        let current = Stamp::of(&path);
+        Self { path, current, pending: None }
+    }
+
+    /// Returns `true` once per change, after the file has settled.
+    pub fn poll(&mut self) -> bool{
+        let Some(stamp) = Stamp::of(&self.path) else {
+            // mid-rewrite or deleted, wait for it to come back
+            self.pending = None;
+            return false;
+        };
+        if Some(stamp) == self.current {
+            self.pending = None;
+            return false;
+        }
+        match self.pending {
+            Some((pending, since)) if pending == stamp => {
+                if since.elapsed() < SETTLE_TIME {
+                    return false;
+                }
+                self.current = Some(stamp);
+                self.pending = None;
+                true
+            },
+            _ => {
+                self.pending = Some((stamp, Instant::now()));
+                false
+            },
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use live::watch::Watcher;
+
+/// How often the sources are looked at, each time stats every file of them.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Rebuilds the `live` crate in the background whenever its sources change.
+pub struct Builder{
+    root: PathBuf,
+    /// Whether to build the release profile, the one `library` is from.
+    release: bool,
+    sources: Watcher,
+    polled: Instant,
+    running: Option<Receiver<Result<(), String>>>,
+    dirty: bool,
+}
+
+impl Builder {
+    /// `root` is the workspace root, the one containing `live/`, and
+    /// `library` the path the host loads the library from.
+    pub fn new(root: impl Into<PathBuf>, library: &Path) -> Self{
+        let root = root.into();
+        let release = release_profile(library).unwrap_or_else(|| {
+            eprintln!(
+                "warning: {} is not in cargo's debug or release directory, rebuilds of live will not be loaded",
+                library.display(),
+            );
+            !cfg!(debug_assertions)
+        });
+        let sources = Watcher::new(root.join("live").join("src"));
+        Self { root, release, sources, polled: Instant::now(), running: None, dirty: false }
+    }
+
+    /// Starts a build if sources changed and reports finished builds.
+    ///
+    /// A failed build yields the compiler output. Changes made while a build
+    /// is running start another one once it is done.
+    pub fn poll(&mut self) -> Option<Result<(), String>>{
+        if self.polled.elapsed() >= POLL_INTERVAL {
+            self.polled = Instant::now();
+            if self.sources.poll() {
+                self.dirty = true;
+            }
+        }
+        if let Some(running) = &self.running {
+            let result = match running.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => Err("build thread died".to_owned()),
+            };
+            self.running = None;
+            return Some(result);
+        }
+        if self.dirty {
+            self.dirty = false;
+            self.running = Some(spawn_build(&self.root, self.release));
+            println!("building live...");
+        }
+        None
+    }
+}
+
+/// Whether `library` was built with the release profile, by the cargo
+/// profile directory it is in.
+fn release_profile(library: &Path) -> Option<bool>{
+    match library.parent()?.file_name()?.to_str()? {
+        "release" => Some(true),
+        "debug" => Some(false),
+        _ => None,
+    }
+}
+
+fn spawn_build(root: &Path, release: bool) -> Receiver<Result<(), String>>{
+    let (send, recv) = mpsc::channel();
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut cmd = Command::new(cargo);
+    cmd.current_dir(root)
+        .args(["build", "-p", "live", "--message-format", "short", "--color", "never"]);
+    // build into the directory the host loads the library from
+    if release {
+        cmd.arg("--release");
+    }
+    std::thread::spawn(move||{
+        let result = match cmd.output() {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(compiler_messages(&String::from_utf8_lossy(&output.stderr))),
+            Err(e) => Err(format!("could not run cargo: {e}")),
+        };
+        _ = send.send(result);
+    });
+    recv
+}
+
+/// Drops cargo's progress lines, leaving the diagnostics.
+fn compiler_messages(stderr: &str) -> String{
+    stderr
+        .lines()
+        .filter(|line| {
+            let line = line.trim_start();
+            !(line.starts_with("Compiling") || line.starts_with("Blocking") || line.starts_with("Building"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
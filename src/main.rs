@@ -1,77 +1,265 @@
-use libloading::*;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
+use libloading::*;
+use live::graphics::Graphics;
+use live::watch;
 
+mod dev;
 
 #[link(name="raylib", kind="dylib")]
 extern "C" {
 
 }
 
+/// Environment variable that overrides where the `live` library is loaded from.
+const LIB_ENV_VAR: &str = "SEE_LIVE_LIB";
 
-macro_rules! reload_fns {
-    ($lib: ident {$($id: ident, $name: literal : $ty: ty,)*}) => {
-        $(
-            let tmp: &mut Option<Symbol<$ty>> = &mut $id;
-            *tmp = $lib.as_ref().unwrap().get($name).ok();
-        )*
-    };
+/// Resolves the path of the `live` library.
+///
+/// In order of precedence: `--lib <path>`, `$SEE_LIVE_LIB`, and finally the
+/// directory of the running executable, which is where cargo puts the dylib
+/// for both debug and release builds.
+fn library_path() -> Result<PathBuf, String>{
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--lib" {
+            return args.next()
+                .map(PathBuf::from)
+                .ok_or_else(|| "`--lib` expects a path".to_owned());
+        }
+        if let Some(path) = arg.strip_prefix("--lib=") {
+            return Ok(path.into());
+        }
+    }
+    if let Some(path) = std::env::var_os(LIB_ENV_VAR) {
+        return Ok(path.into());
+    }
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("could not locate the executable: {e}"))?;
+    let dir = exe.parent()
+        .ok_or_else(|| format!("executable {} has no parent directory", exe.display()))?;
+    Ok(dir.join(library_filename("live")))
 }
 
-macro_rules! reload {
-    (
-        $lib: ident,
-        $should_close: ident,
-        $should_reload: ident,
-        $update: ident,
-    ) => {
-        unsafe{
-            $update = None;
-            $should_close = None;
-            $should_reload = None;
-            $lib = None;
-            _ = $lib;
-            $lib = Library::new(format!("target/debug/{}", library_filename("live").to_str().unwrap())).ok();
-            reload_fns!(
-                $lib{
-                    $should_close, b"should_close\0": extern fn(&mut live::State)->bool,
-                    $should_reload, b"should_reload\0": extern fn(&mut live::State)->bool,
-                    $update, b"update\0": extern fn(&mut live::State),
-                }
-            );
+/// The file to show, the first argument that isn't an option.
+fn file_path() -> Option<PathBuf>{
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--lib" {
+            args.next();
+        } else if !arg.starts_with("--") {
+            return Some(arg.into());
+        }
+    }
+    None
+}
+
+fn has_flag(flag: &str) -> bool{
+    std::env::args().skip(1).any(|arg| arg == flag)
+}
+
+fn symbol_name(name: &[u8]) -> &str{
+    std::str::from_utf8(name.strip_suffix(b"\0").unwrap_or(name)).unwrap_or("?")
+}
+
+/// Copies the library to a path unique to this process and generation.
+///
+/// Loading the copy leaves the original free to be overwritten by the linker,
+/// and the fresh path keeps `dlopen` from handing back the already loaded
+/// library.
+fn shadow_copy(path: &Path, generation: u32) -> Result<PathBuf, String>{
+    let name = format!("live-{}-{generation}", std::process::id());
+    let copy = std::env::temp_dir().join(library_filename(name));
+    std::fs::copy(path, &copy)
+        .map_err(|e| format!("could not copy {} to {}: {e}", path.display(), copy.display()))?;
+    Ok(copy)
+}
+
+macro_rules! live_fns {
+    ($($id: ident, $name: literal : $ty: ty,)*) => {
+        /// Functions exported by the `live` library.
+        struct Fns{
+            $($id: $ty,)*
+        }
+        impl Fns {
+            /// The pointers are only valid while `lib` stays loaded.
+            unsafe fn load(lib: &Library, path: &Path) -> Result<Self, String>{
+                $(
+                    let $id: $ty = match lib.get::<$ty>($name) {
+                        Ok(symbol) => *symbol,
+                        Err(e) => return Err(format!(
+                            "{} does not export `{}`: {e}",
+                            path.display(),
+                            symbol_name($name),
+                        )),
+                    };
+                )*
+                Ok(Self { $($id,)* })
+            }
         }
     };
 }
 
+live_fns!{
+    abi_hash, b"abi_hash\0": fn()->u64,
+    init, b"init\0": for<'g> fn(&'g mut Graphics, Option<&Path>)->Result<Box<live::State<'g>>, String>,
+    drop_state, b"drop_state\0": fn(Box<live::State>),
+    save_state, b"save_state\0": fn(&live::State)->Vec<u8>,
+    load_state, b"load_state\0": fn(&mut live::State, &[u8])->Result<(), String>,
+    should_close, b"should_close\0": fn(&mut live::State)->bool,
+    should_reload, b"should_reload\0": fn(&mut live::State)->bool,
+    try_update, b"try_update\0": fn(&mut live::State)->Result<(), String>,
+    draw_overlay_frame, b"draw_overlay_frame\0": fn(&mut live::State),
+    set_overlay, b"set_overlay\0": fn(&mut live::State, Option<String>),
+}
+
+/// A loaded copy of the `live` library.
+struct Live{
+    fns: Fns,
+    /// Unloaded in `drop`, the function pointers in `fns` dangle afterwards.
+    lib: ManuallyDrop<Library>,
+    copy: PathBuf,
+}
+
+impl Live {
+    fn load(path: &Path, generation: u32) -> Result<Self, String>{
+        let copy = shadow_copy(path, generation)?;
+        let lib = match unsafe { Library::new(&copy) } {
+            Ok(lib) => lib,
+            Err(e) => {
+                _ = std::fs::remove_file(&copy);
+                return Err(format!("could not load {}: {e}", path.display()));
+            },
+        };
+        match unsafe { Fns::load(&lib, path) } {
+            Ok(fns) => Ok(Self {
+                fns,
+                lib: ManuallyDrop::new(lib),
+                copy,
+            }),
+            Err(e) => {
+                drop(lib);
+                _ = std::fs::remove_file(&copy);
+                Err(e)
+            },
+        }
+    }
+}
+
+impl Deref for Live {
+    type Target = Fns;
+
+    fn deref(&self) -> &Fns {
+        &self.fns
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.lib);
+        }
+        _ = std::fs::remove_file(&self.copy);
+    }
+}
+
+/// `load_state`, reporting a failure in the overlay.
+fn restore(lib: &Live, state: &mut live::State, saved: &[u8]){
+    if let Err(e) = (lib.load_state)(state, saved) {
+        let msg = format!("could not restore the state after reloading: {e}");
+        eprintln!("{msg}");
+        (lib.set_overlay)(state, Some(msg));
+    }
+}
+
+fn fail(path: &Path, err: String) -> ! {
+    eprintln!("error: {err}");
+    eprintln!("hint: build the library with `cargo build -p live`, or point `--lib`/${LIB_ENV_VAR} at it (tried {})", path.display());
+    std::process::exit(1);
+}
+
 
 fn main() {
 
-    let mut lib: Option<Library>;
-    let mut update;
-    let mut should_close;
-    let mut should_reload;
-
-    reload!(
-        lib,
-        should_close,
-        should_reload,
-        update,
-    );
-
-    let mut g = live::graphics::Graphics::init(800, 450, "See");
-    let mut state = live::init(&mut g);
-
-    while !should_close.as_ref().unwrap()(&mut state) {
-        update.as_ref().unwrap()(&mut state);
-
-        let reload = should_reload.as_ref().unwrap()(&mut state);
-        if reload {
-            reload!(
-                lib,
-                should_close,
-                should_reload,
-                update,
-            );
-            println!("reloaded!");
+    let path = library_path().unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    });
+
+    let mut generation = 0;
+    let mut lib = Live::load(&path, generation).unwrap_or_else(|e| fail(&path, e));
+    let mut watcher = watch::Watcher::new(&path);
+    // `--dev` rebuilds the library from source, the host is built from the workspace root
+    let mut builder = has_flag("--dev").then(|| dev::Builder::new(env!("CARGO_MANIFEST_DIR"), &path));
+
+    let mut g = Graphics::init(800, 450, "See");
+    // created and dropped by the loaded library, the statically linked `live`
+    // may disagree on its layout
+    let mut state = ManuallyDrop::new((lib.init)(&mut g, file_path().as_deref()).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }));
+    let mut state_abi = (lib.abi_hash)();
+    // set when `update` of the loaded library panicked, cleared by the next reload
+    let mut crashed = false;
+
+    while !(lib.should_close)(&mut state) {
+        if crashed {
+            (lib.draw_overlay_frame)(&mut state);
+        } else if let Err(report) = (lib.try_update)(&mut state) {
+            eprintln!("update panicked: {report}");
+            let msg = format!("update panicked, waiting for a fixed library\n\n{report}");
+            (lib.set_overlay)(&mut state, Some(msg));
+            crashed = true;
+        }
+
+        if let Some(result) = builder.as_mut().and_then(dev::Builder::poll) {
+            // on success the watcher picks up the new library, a panic report
+            // stays up until then
+            if result.is_err() || !crashed {
+                (lib.set_overlay)(&mut state, result.err());
+            }
+        }
+
+        let requested = (lib.should_reload)(&mut state);
+        if watcher.poll() || requested {
+            generation += 1;
+            match Live::load(&path, generation) {
+                Ok(new) => 'reload: {
+                    if (new.abi_hash)() != state_abi {
+                        // `State` may have a different layout, rebuild it with the new library,
+                        // the file shown is opened again from `saved`
+                        let saved = (lib.save_state)(&state);
+                        (lib.drop_state)(ManuallyDrop::into_inner(state));
+                        match (new.init)(&mut g, None) {
+                            Ok(new_state) => state = ManuallyDrop::new(new_state),
+                            Err(e) => {
+                                // e.g. an asset is missing, rebuild it with the old library instead
+                                eprintln!("reload failed, keeping the old library: {e}");
+                                state = ManuallyDrop::new((lib.init)(&mut g, None).unwrap_or_else(|e| {
+                                    eprintln!("error: {e}");
+                                    std::process::exit(1);
+                                }));
+                                restore(&lib, &mut state, &saved);
+                                (lib.set_overlay)(&mut state, Some(format!("could not start the new library: {e}")));
+                                break 'reload;
+                            },
+                        }
+                        state_abi = (new.abi_hash)();
+                        restore(&new, &mut state, &saved);
+                    }
+                    lib = new;
+                    if crashed {
+                        crashed = false;
+                        (lib.set_overlay)(&mut state, None);
+                    }
+                    println!("reloaded!");
+                },
+                Err(e) => eprintln!("reload failed, keeping the old library: {e}"),
+            }
         }
     }
+    (lib.drop_state)(ManuallyDrop::into_inner(state));
 }